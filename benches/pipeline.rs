@@ -0,0 +1,202 @@
+//! Throughput of the in-process request pipeline (no sockets, no epoll), then of
+//! keep-alive round trips over loopback through the event loop.
+//!
+//! Run with `cargo bench --features bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use http_rust::https::{HttpMethod, StatusCode, response_with_body};
+use http_rust::router::{ListenAddr, Router, RouterConfig};
+
+const WARMUP: u64 = 2_000;
+const ITERATIONS: u64 = 50_000;
+const SAMPLES: usize = 5;
+const ROUND_TRIPS: u64 = 20_000;
+const MANY_ROUTES: usize = 1_000;
+
+/// The system allocator, counting every allocation so runs can report them per request.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn bench(router: &mut Router, name: &str, raw: &[u8]) {
+    router.bench_pipeline(8080, raw, WARMUP);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    router.bench_pipeline(8080, raw, ITERATIONS);
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / ITERATIONS as f64;
+
+    let mut rates: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            router
+                .bench_pipeline(8080, raw, ITERATIONS)
+                .requests_per_sec()
+        })
+        .collect();
+    rates.sort_by(f64::total_cmp);
+
+    let median = rates[SAMPLES / 2];
+    let per_request = Duration::from_secs_f64(1.0 / median);
+    println!(
+        "{name:<24} {median:>12.0} req/s  ({per_request:?}/req, min {:.0}, max {:.0}, {allocations:.1} allocs/req)",
+        rates[0],
+        rates[SAMPLES - 1]
+    );
+}
+
+/// Sends `raw` over one keep-alive connection `ROUND_TRIPS` times, running the event
+/// loop until each response (ending in `"OK"`) is back.
+fn bench_loopback(name: &str, raw: &[u8]) {
+    let port = {
+        let probe = TcpListener::bind("127.0.0.1:0").expect("should bind an ephemeral port");
+        probe.local_addr().expect("listener has an address").port()
+    };
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let config = RouterConfig {
+        epoll_wait: Duration::from_millis(1),
+        ..RouterConfig::default()
+    };
+    let mut router = Router::with_config(&[ListenAddr::new(addr)], config);
+    router.disable_access_log();
+    router.add_route(port, "/health", vec![HttpMethod::Get], |req, _data| {
+        response_with_body(&req.version, StatusCode::Ok, "text/plain", b"OK".to_vec())
+    });
+
+    let mut client = TcpStream::connect(addr).expect("should connect");
+    client.set_nodelay(true).expect("should disable Nagle");
+    client
+        .set_nonblocking(true)
+        .expect("stream should become non-blocking");
+    let mut buf = [0u8; 4096];
+    let mut round_trip = |router: &mut Router| {
+        client.write_all(raw).expect("request should be sent");
+        loop {
+            router
+                .handle_connections()
+                .expect("event loop should not fail");
+            match client.read(&mut buf) {
+                Ok(0) => panic!("server closed the connection"),
+                Ok(n) if buf[..n].ends_with(b"OK") => return,
+                Ok(_) | Err(_) => {}
+            }
+        }
+    };
+    for _ in 0..WARMUP {
+        round_trip(&mut router);
+    }
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUND_TRIPS {
+        round_trip(&mut router);
+    }
+    let elapsed = start.elapsed();
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / ROUND_TRIPS as f64;
+    println!(
+        "{name:<24} {:>12.0} req/s  ({:?}/req, {allocations:.1} allocs/req)",
+        ROUND_TRIPS as f64 / elapsed.as_secs_f64(),
+        elapsed / ROUND_TRIPS as u32,
+    );
+}
+
+fn main() {
+    let mut router = Router::new_on_ports(&[]);
+    router.add_route(8080, "/health", vec![HttpMethod::Get], |req, _data| {
+        response_with_body(&req.version, StatusCode::Ok, "text/plain", b"OK".to_vec())
+    });
+    router.add_route(
+        8080,
+        "/users/:id/posts/:post",
+        vec![HttpMethod::Get],
+        |req, data| {
+            let body = format!("{:?}", data.path_value).into_bytes();
+            response_with_body(&req.version, StatusCode::Ok, "text/plain", body)
+        },
+    );
+    router.add_route(8080, "/upload", vec![HttpMethod::Post], |req, data| {
+        let body = data.body.len().to_string().into_bytes();
+        response_with_body(&req.version, StatusCode::Created, "text/plain", body)
+    });
+
+    bench(
+        &mut router,
+        "get_static_route",
+        b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    bench(
+        &mut router,
+        "get_path_params",
+        b"GET /users/42/posts/7?sort=asc HTTP/1.1\r\nHost: localhost\r\nAccept: text/html;q=0.9, */*\r\n\r\n",
+    );
+    bench(
+        &mut router,
+        "post_chunked_body",
+        b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+    );
+    bench(
+        &mut router,
+        "not_found",
+        b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+
+    // Routing cost once a port has many routes: the matched route is the last one
+    // registered, so a linear scan would try every other pattern first.
+    let mut many = Router::new_on_ports(&[]);
+    for i in 0..MANY_ROUTES {
+        many.add_route(
+            8080,
+            &format!("/api/v1/resource{i}/:id"),
+            vec![HttpMethod::Get],
+            |req, _data| {
+                response_with_body(&req.version, StatusCode::Ok, "text/plain", b"OK".to_vec())
+            },
+        );
+    }
+    bench(
+        &mut many,
+        "1000_routes_last_match",
+        format!(
+            "GET /api/v1/resource{}/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            MANY_ROUTES - 1
+        )
+        .as_bytes(),
+    );
+    bench(
+        &mut many,
+        "1000_routes_not_found",
+        b"GET /api/v1/missing/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+
+    bench_loopback(
+        "loopback_keep_alive",
+        b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+}
@@ -1,16 +1,109 @@
+use std::borrow::Cow;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::FileExt;
 use std::time::Instant;
 
-use crate::https::StatusCode;
+use crate::https::{
+    BodyStream, FileBody, HeaderMap, LAST_CHUNK, StatusCode, StreamFraming, encode_chunk,
+};
 use crate::router::PendingRequest;
 use crate::router::ReadOutcome;
+use crate::utils::helpers::sendfile_nonblocking;
 
 #[derive(Debug)]
 pub struct Conn {
     pub local_port: u16,
+    /// Address of the client, when it connected over IP.
+    pub peer_addr: Option<SocketAddr>,
     pub in_buf: Vec<u8>,
     pub out_buf: Vec<u8>,
     pub state: ConnState,
     pub last_activity: Instant,
+    /// When the request head being read must be complete; `None` outside
+    /// `ReadingHeaders` and on a kept-alive connection until its next request starts.
+    pub header_deadline: Option<Instant>,
+    /// Drop the connection as soon as `out_buf` has been flushed.
+    pub close_after_flush: bool,
+    pub options: ParseOptions,
+    /// Session whose cookie this connection presented, for per-session limits.
+    pub session_id: Option<String>,
+    /// Remaining body of a streamed response, pulled into `out_buf` as it drains.
+    pub stream: Option<(BodyStream, StreamFraming)>,
+    /// Bytes the streamed response may still produce before it is aborted.
+    pub stream_cap: Option<usize>,
+    /// File body still to be sent once `out_buf` (the head) has drained.
+    pub file: Option<FileBody>,
+    /// How much of the request head in `in_buf` has already been searched for its
+    /// terminator and bare LFs, so each read only searches the bytes it added.
+    head_scanned: usize,
+}
+
+/// What `Conn::send_file` managed before returning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileProgress {
+    /// The whole file has been sent.
+    Done,
+    /// The socket is full; wait for it to become writable again.
+    Blocked,
+    /// `sendfile` is unavailable, so the next piece was read into `out_buf`.
+    Buffered,
+}
+
+/// Largest piece handed to one `sendfile` call.
+const SENDFILE_MAX: u64 = 1 << 20;
+/// Piece size when a file has to go through `out_buf`.
+const FILE_BUFFER_SIZE: u64 = 64 * 1024;
+
+/// Request-parsing knobs the router copies into every connection it accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Treat an HTTP/1.0 request with a body-bearing method, no `Content-Length` or
+    /// `Transfer-Encoding`, and an explicit `Connection: close` as having a body that
+    /// ends at EOF. Off by default: such a request otherwise has no body.
+    pub read_body_until_close: bool,
+    /// What to do with absolute-form targets such as `GET http://host/x HTTP/1.1`.
+    pub absolute_form: AbsoluteFormPolicy,
+    /// Largest request body accepted; bigger ones are answered with 413.
+    pub max_body_bytes: Option<usize>,
+    /// Largest request head (request line, headers and the blank line) accepted;
+    /// bigger ones are answered with 431, without waiting for the terminator.
+    pub max_header_bytes: Option<usize>,
+    /// Most header lines accepted in one request; more are answered with 431.
+    pub max_header_count: Option<usize>,
+    /// Answer with 400 when a line in the request head or a chunk size or trailer
+    /// line ends with a bare LF instead of CRLF; see `find_bare_lf`.
+    pub strict_line_endings: bool,
+}
+
+/// Default for `ParseOptions::max_header_bytes`.
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+/// Default for `ParseOptions::max_header_count`.
+pub const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            read_body_until_close: false,
+            absolute_form: AbsoluteFormPolicy::Reject,
+            max_body_bytes: None,
+            max_header_bytes: Some(DEFAULT_MAX_HEADER_BYTES),
+            max_header_count: Some(DEFAULT_MAX_HEADER_COUNT),
+            strict_line_endings: true,
+        }
+    }
+}
+
+/// Handling of absolute-form request targets. Schemes other than `http`/`https` are
+/// answered with 400 under either policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbsoluteFormPolicy {
+    /// Answer with 400; this server is not a proxy.
+    Reject,
+    /// Route on the target's path and take `Host` from its authority.
+    Accept,
 }
 
 #[derive(Debug)]
@@ -23,16 +116,175 @@ pub enum ConnState {
     ReadingBodyChunked {
         header_end: usize,
     },
+    ReadingBodyUntilClose {
+        header_end: usize,
+    },
     Responding,
+    Upgraded,
+}
+
+/// A request head split into its request line and header fields.
+pub(crate) struct RequestHead<'a> {
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+    pub headers: HeaderMap,
+}
+
+/// Splits a request head (everything before the body) into a `RequestHead`. Framing
+/// detection and `parse_request` both start here, so they always agree on which
+/// fields a request carries. Lines without a colon are ignored; a field name with
+/// whitespace before its colon is rejected (RFC 9112 5.1), since other parsers may
+/// read `Content-Length : 5` as a different field or as no field at all.
+pub(crate) fn parse_head<'a>(
+    header_bytes: &'a [u8],
+    options: &ParseOptions,
+) -> Result<RequestHead<'a>, (StatusCode, String)> {
+    let bad_request = |reason: &str| (StatusCode::BadRequest, reason.to_string());
+    let text = std::str::from_utf8(header_bytes)
+        .map_err(|_| bad_request("request headers are not valid UTF-8"))?;
+    let mut lines = text.split("\r\n");
+
+    let request_line = lines
+        .next()
+        .ok_or_else(|| bad_request("missing request line"))?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| bad_request("missing HTTP method"))?;
+    let target = parts
+        .next()
+        .ok_or_else(|| bad_request("missing request path"))?;
+    let version = parts
+        .next()
+        .ok_or_else(|| bad_request("missing HTTP version"))?;
+    if parts.next().is_some() {
+        return Err(bad_request("request line has extra fields"));
+    }
+
+    let mut headers = HeaderMap::default();
+    let mut count = 0usize;
+    for line in unfold_header_lines(lines).map_err(|reason| bad_request(&reason))? {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if name.is_empty() || name.trim() != name {
+            return Err(bad_request("header name has surrounding whitespace"));
+        }
+        count += 1;
+        if options.max_header_count.is_some_and(|max| count > max) {
+            return Err((
+                StatusCode::RequestHeaderFieldsTooLarge,
+                "request has too many header fields".to_string(),
+            ));
+        }
+        headers.append(name, value);
+    }
+
+    Ok(RequestHead {
+        method,
+        target,
+        version,
+        headers,
+    })
+}
+
+/// Header lines up to the blank line that ends them, with obsolete line folding
+/// (RFC 9112 5.2) undone: a line starting with SP or HTAB continues the previous
+/// field, joined to it by a single space. A continuation with no field before it is
+/// an error, since it could only belong to the request line.
+fn unfold_header_lines<'a>(
+    lines: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Cow<'a, str>>, String> {
+    let mut unfolded: Vec<Cow<'a, str>> = Vec::new();
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        if !line.starts_with([' ', '\t']) {
+            unfolded.push(Cow::Borrowed(line));
+            continue;
+        }
+        let Some(previous) = unfolded.last_mut() else {
+            return Err("folded header line without a field to continue".to_string());
+        };
+        let joined = format!("{} {}", previous.trim_end(), line.trim());
+        *previous = Cow::Owned(joined);
+    }
+    Ok(unfolded)
 }
 
+/// Position of the first LF at or after `from` in `bytes` that is not preceded by CR.
+/// The scan is a single pass over those bytes, with the byte before `bytes` taken to
+/// be no CR, so a leading LF counts as bare. Recipients disagree on whether a bare LF
+/// ends a line, which lets a request look different to a proxy and to this server
+/// (smuggling).
+pub(crate) fn find_bare_lf(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len()).find(|&i| bytes[i] == b'\n' && (i == 0 || bytes[i - 1] != b'\r'))
+}
+
+/// A decoded chunked body, its trailer fields and the number of input bytes it spanned.
+type DecodedChunked = (Vec<u8>, HeaderMap, usize);
+
+/// Fields a client may not send as trailers (RFC 9110 6.5.1): they control framing,
+/// routing, authentication or how the request is handled, all decided before the body.
+const FORBIDDEN_TRAILERS: &[&str] = &[
+    "authorization",
+    "cache-control",
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "content-type",
+    "cookie",
+    "expect",
+    "host",
+    "if-match",
+    "if-modified-since",
+    "if-none-match",
+    "if-range",
+    "if-unmodified-since",
+    "max-forwards",
+    "pragma",
+    "proxy-authorization",
+    "range",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
 enum BodyFraming {
     ContentLength(usize),
     Chunked,
+    UntilClose,
 }
 
 impl Conn {
+    pub fn new(local_port: u16, options: ParseOptions) -> Self {
+        Self {
+            local_port,
+            peer_addr: None,
+            in_buf: Vec::new(),
+            out_buf: Vec::new(),
+            state: ConnState::ReadingHeaders,
+            last_activity: Instant::now(),
+            header_deadline: None,
+            close_after_flush: false,
+            options,
+            session_id: None,
+            stream: None,
+            stream_cap: None,
+            file: None,
+            head_scanned: 0,
+        }
+    }
+
     pub fn read_outcome(&mut self, new_bytes: &[u8]) -> ReadOutcome {
+        if self.close_after_flush {
+            // Nothing read after the final response will ever be answered.
+            return ReadOutcome::Pending;
+        }
         self.in_buf.extend_from_slice(new_bytes);
 
         match self.state {
@@ -42,16 +294,167 @@ impl Conn {
                 content_length,
             } => self.read_body_content_length(header_end, content_length),
             ConnState::ReadingBodyChunked { header_end } => self.read_body_chunked(header_end),
-            ConnState::Responding => ReadOutcome::Pending,
+            ConnState::ReadingBodyUntilClose { header_end } => {
+                if self.exceeds_max_body(self.in_buf.len() - header_end) {
+                    return Self::body_too_large();
+                }
+                ReadOutcome::Pending
+            }
+            ConnState::Responding | ConnState::Upgraded => ReadOutcome::Pending,
+        }
+    }
+
+    /// Completes a request whose body is delimited by the peer closing its side.
+    /// Returns `None` when the connection was not waiting for EOF.
+    pub fn finish_at_eof(&mut self) -> Option<ReadOutcome> {
+        let ConnState::ReadingBodyUntilClose { header_end } = self.state else {
+            return None;
+        };
+
+        let request_end = self.in_buf.len();
+        Some(ReadOutcome::Ready(self.build_pending_request(
+            header_end,
+            request_end,
+            None,
+        )))
+    }
+
+    /// Sends the file body to socket `fd` with `sendfile`, falling back to reading it
+    /// into `out_buf` piece by piece where the kernel refuses `sendfile` for the file.
+    pub fn send_file(&mut self, fd: RawFd) -> io::Result<FileProgress> {
+        let Some(body) = self.file.as_mut() else {
+            return Ok(FileProgress::Done);
+        };
+
+        while body.remaining > 0 {
+            if !body.buffered {
+                let count = body.remaining.min(SENDFILE_MAX) as usize;
+                match sendfile_nonblocking(fd, body.file.as_raw_fd(), body.offset, count) {
+                    Ok(Some(0)) => return Err(file_shrank()),
+                    Ok(Some(n)) => {
+                        body.offset += n as u64;
+                        body.remaining -= n as u64;
+                        continue;
+                    }
+                    Ok(None) => return Ok(FileProgress::Blocked),
+                    Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) => {
+                        body.buffered = true;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            let mut piece = vec![0u8; body.remaining.min(FILE_BUFFER_SIZE) as usize];
+            let n = body.file.read_at(&mut piece, body.offset)?;
+            if n == 0 {
+                return Err(file_shrank());
+            }
+            self.out_buf.extend_from_slice(&piece[..n]);
+            body.offset += n as u64;
+            body.remaining -= n as u64;
+            return Ok(FileProgress::Buffered);
+        }
+
+        self.file = None;
+        Ok(FileProgress::Done)
+    }
+
+    /// Moves the next piece of the streamed response into `out_buf`, framed for the
+    /// wire. Returns `false` once there is nothing left to send.
+    pub fn fill_from_stream(&mut self) -> bool {
+        let Some((stream, framing)) = self.stream.as_mut() else {
+            return !self.out_buf.is_empty();
+        };
+        let framing = *framing;
+        // An empty piece would read as the terminating chunk, so skip those.
+        let chunk = stream.find(|chunk| !chunk.is_empty());
+
+        if let (Some(chunk), Some(cap)) = (&chunk, self.stream_cap.as_mut()) {
+            if chunk.len() > *cap {
+                crate::error!(
+                    "streamed response exceeded the maximum body size; aborting",
+                    "local_port" => self.local_port
+                );
+                self.stream = None;
+                self.close_after_flush = true;
+                return !self.out_buf.is_empty();
+            }
+            *cap -= chunk.len();
+        }
+
+        match (chunk, framing) {
+            (Some(chunk), StreamFraming::Chunked) => encode_chunk(&mut self.out_buf, &chunk),
+            (Some(chunk), StreamFraming::Close) => self.out_buf.extend_from_slice(&chunk),
+            (Some(chunk), StreamFraming::Length(remaining)) => {
+                let take = chunk.len().min(remaining as usize);
+                self.out_buf.extend_from_slice(&chunk[..take]);
+                if take < chunk.len() {
+                    crate::warn!("stream exceeded its length hint; truncating", "hint_left" => remaining);
+                    self.stream = None;
+                } else if let Some((_, framing)) = self.stream.as_mut() {
+                    *framing = StreamFraming::Length(remaining - take as u64);
+                }
+            }
+            (None, StreamFraming::Chunked) => {
+                self.stream = None;
+                self.out_buf.extend_from_slice(LAST_CHUNK);
+            }
+            (None, StreamFraming::Close) => {
+                self.stream = None;
+                self.close_after_flush = true;
+            }
+            (None, StreamFraming::Length(remaining)) => {
+                self.stream = None;
+                if remaining > 0 {
+                    // The client is still waiting for bytes that will never come.
+                    crate::warn!("stream ended short of its length hint", "missing" => remaining);
+                    self.close_after_flush = true;
+                }
+            }
         }
+        !self.out_buf.is_empty()
+    }
+
+    pub fn is_reading_until_close(&self) -> bool {
+        matches!(self.state, ConnState::ReadingBodyUntilClose { .. })
     }
 
     fn read_headers(&mut self) -> ReadOutcome {
-        let Some(header_end) = self.find_header_end() else {
+        let scanned = self.head_scanned.min(self.in_buf.len());
+        let header_end = self.find_header_end(scanned);
+        let head_len = header_end.unwrap_or(self.in_buf.len());
+        if self
+            .options
+            .max_header_bytes
+            .is_some_and(|max| head_len > max)
+        {
+            return ReadOutcome::Error {
+                status: StatusCode::RequestHeaderFieldsTooLarge,
+                reason: "request head exceeds the configured maximum".to_string(),
+            };
+        }
+        // Checked on every read, so a head using bare LFs throughout, which never
+        // contains the CRLF CRLF terminator, is rejected as soon as its first line ends.
+        if self.options.strict_line_endings
+            && find_bare_lf(&self.in_buf[..head_len], scanned).is_some()
+        {
+            return ReadOutcome::Error {
+                status: StatusCode::BadRequest,
+                reason: "request head contains a bare LF line ending".to_string(),
+            };
+        }
+        let Some(header_end) = header_end else {
+            self.head_scanned = head_len;
             return ReadOutcome::Pending;
         };
+        // The next head starts wherever this request ends.
+        self.head_scanned = 0;
 
-        let framing = match Self::parse_body_framing(&self.in_buf[..header_end]) {
+        let head = match parse_head(&self.in_buf[..header_end], &self.options) {
+            Ok(head) => head,
+            Err((status, reason)) => return ReadOutcome::Error { status, reason },
+        };
+        let framing = match Self::body_framing(&head, &self.options) {
             Ok(v) => v,
             Err(reason) => {
                 return ReadOutcome::Error {
@@ -60,21 +463,33 @@ impl Conn {
                 };
             }
         };
+        let expects_continue = Self::expects_continue(&head);
 
         match framing {
             BodyFraming::ContentLength(0) => {
-                ReadOutcome::Ready(self.build_pending_request(header_end, Vec::new()))
+                ReadOutcome::Ready(self.build_pending_request(header_end, header_end, None))
+            }
+            BodyFraming::ContentLength(content_length) if self.exceeds_max_body(content_length) => {
+                Self::body_too_large()
             }
             BodyFraming::ContentLength(content_length) => {
                 self.state = ConnState::ReadingBodyContentLength {
                     header_end,
                     content_length,
                 };
-                self.read_body_content_length(header_end, content_length)
+                let outcome = self.read_body_content_length(header_end, content_length);
+                self.send_continue_if_expected(expects_continue, &outcome);
+                outcome
             }
             BodyFraming::Chunked => {
                 self.state = ConnState::ReadingBodyChunked { header_end };
-                self.read_body_chunked(header_end)
+                let outcome = self.read_body_chunked(header_end);
+                self.send_continue_if_expected(expects_continue, &outcome);
+                outcome
+            }
+            BodyFraming::UntilClose => {
+                self.state = ConnState::ReadingBodyUntilClose { header_end };
+                self.read_outcome(&[])
             }
         }
     }
@@ -89,82 +504,139 @@ impl Conn {
             return ReadOutcome::Pending;
         }
 
-        ReadOutcome::Ready(
-            self.build_pending_request(header_end, self.in_buf[header_end..total_len].to_vec()),
-        )
+        ReadOutcome::Ready(self.build_pending_request(header_end, total_len, None))
     }
 
     fn read_body_chunked(&mut self, header_end: usize) -> ReadOutcome {
         let body_and_trailers = &self.in_buf[header_end..];
-        let (decoded_body, _consumed) = match Self::decode_chunked_body(body_and_trailers) {
+        let decoded = Self::decode_chunked_body(body_and_trailers, &self.options);
+        let (decoded_body, trailers, consumed) = match decoded {
             Ok(Some(v)) => v,
             Ok(None) => return ReadOutcome::Pending,
-            Err(reason) => {
-                return ReadOutcome::Error {
-                    status: StatusCode::BadRequest,
-                    reason,
-                };
-            }
+            Err((status, reason)) => return ReadOutcome::Error { status, reason },
         };
 
-        ReadOutcome::Ready(self.build_pending_request(header_end, decoded_body))
+        let mut pending =
+            self.build_pending_request(header_end, header_end + consumed, Some(decoded_body));
+        pending.trailers = trailers;
+        ReadOutcome::Ready(pending)
     }
 
-    fn build_pending_request(&mut self, header_end: usize, body_bytes: Vec<u8>) -> PendingRequest {
-        PendingRequest {
-            header_bytes: self.in_buf[..header_end].to_vec(),
-            body_bytes,
-            local_port: self.local_port,
+    /// Whether the client sent `Expect: 100-continue` and waits for an interim
+    /// `100 Continue` before sending the body. HTTP/1.0 clients never wait.
+    fn expects_continue(head: &RequestHead) -> bool {
+        head.version == "HTTP/1.1"
+            && head
+                .headers
+                .get("expect")
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Queues the interim `100 Continue` when `expected` (see `expects_continue`) and
+    /// the body has yet to arrive. Runs once per request, right after its headers are
+    /// parsed.
+    fn send_continue_if_expected(&mut self, expected: bool, outcome: &ReadOutcome) {
+        if expected && matches!(outcome, ReadOutcome::Pending) {
+            self.out_buf
+                .extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
         }
     }
 
-    fn find_header_end(&mut self) -> Option<usize> {
-        self.in_buf
-            .windows(4)
-            .position(|w| w == b"\r\n\r\n")
-            .map(|i| i + 4)
+    /// True while the connection is still receiving a request (as opposed to answering
+    /// one or running an upgraded protocol).
+    pub fn is_reading_request(&self) -> bool {
+        !matches!(self.state, ConnState::Responding | ConnState::Upgraded)
     }
 
-    fn parse_body_framing(header_bytes: &[u8]) -> Result<BodyFraming, String> {
-        let text = std::str::from_utf8(header_bytes)
-            .map_err(|_| "request headers are not valid UTF-8".to_string())?;
-        let mut lines = text.split("\r\n");
+    /// True once some of a request has arrived but not all of it: a partial head or a
+    /// body still on its way. A kept-alive connection waiting for its next request is
+    /// not mid-request.
+    pub fn is_mid_request(&self) -> bool {
+        match self.state {
+            ConnState::ReadingHeaders => !self.in_buf.is_empty(),
+            _ => self.is_reading_request(),
+        }
+    }
 
-        let _ = lines
-            .next()
-            .ok_or_else(|| "missing request line".to_string())?;
+    fn exceeds_max_body(&self, body_len: usize) -> bool {
+        self.options
+            .max_body_bytes
+            .is_some_and(|max| body_len > max)
+    }
 
-        let mut content_length: Option<usize> = None;
-        let mut transfer_encoding: Option<String> = None;
+    fn body_too_large() -> ReadOutcome {
+        ReadOutcome::Error {
+            status: StatusCode::PayloadTooLarge,
+            reason: "request body exceeds the configured maximum".to_string(),
+        }
+    }
 
-        for line in lines {
-            if line.is_empty() {
-                break;
+    /// Moves the request ending at `request_end` out of `in_buf`, so any bytes the
+    /// client sent after it (e.g. data for an upgraded protocol) stay buffered. The head
+    /// keeps `in_buf`'s allocation and the body is split off it, unless a decoded body
+    /// is given to use instead.
+    fn build_pending_request(
+        &mut self,
+        header_end: usize,
+        request_end: usize,
+        decoded_body: Option<Vec<u8>>,
+    ) -> PendingRequest {
+        let mut header_bytes = if request_end == self.in_buf.len() {
+            mem::take(&mut self.in_buf)
+        } else {
+            let rest = self.in_buf.split_off(request_end);
+            mem::replace(&mut self.in_buf, rest)
+        };
+        let body_bytes = match decoded_body {
+            Some(body) => {
+                header_bytes.truncate(header_end);
+                body
             }
+            None => header_bytes.split_off(header_end),
+        };
+        PendingRequest {
+            header_bytes,
+            body_bytes,
+            trailers: HeaderMap::default(),
+            local_port: self.local_port,
+        }
+    }
 
-            let Some((name, value)) = line.split_once(':') else {
-                continue;
-            };
+    /// End of the CRLF CRLF terminator in `in_buf`, searching from `scanned` but backing
+    /// up three bytes in case the terminator straddles the previous read.
+    fn find_header_end(&self, scanned: usize) -> Option<usize> {
+        let from = scanned.saturating_sub(3);
+        self.in_buf[from..]
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|i| from + i + 4)
+    }
 
-            if !name.eq_ignore_ascii_case("Content-Length") {
-                if name.eq_ignore_ascii_case("Transfer-Encoding") {
-                    if transfer_encoding.is_some() {
-                        return Err("duplicate Transfer-Encoding header".to_string());
-                    }
-                    transfer_encoding = Some(value.trim().to_ascii_lowercase());
+    /// Framing of the body that follows `head`. This is the only place that judges
+    /// `Content-Length` and `Transfer-Encoding`: duplicates, a combination of both, and
+    /// values that are not a plain length or `chunked` are all rejected.
+    fn body_framing(head: &RequestHead, options: &ParseOptions) -> Result<BodyFraming, String> {
+        let mut lengths = head.headers.get_all("content-length");
+        let content_length = lengths.next();
+        if lengths.next().is_some() {
+            return Err("duplicate Content-Length header".to_string());
+        }
+        let content_length = content_length
+            .map(|value| {
+                // `usize::from_str` would accept a leading `+`.
+                if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err("Content-Length must be a non-negative integer".to_string());
                 }
-                continue;
-            }
+                value
+                    .parse::<usize>()
+                    .map_err(|_| "Content-Length is too large".to_string())
+            })
+            .transpose()?;
 
-            if content_length.is_some() {
-                return Err("duplicate Content-Length header".to_string());
-            }
-
-            let parsed = value
-                .trim()
-                .parse::<usize>()
-                .map_err(|_| "Content-Length must be a non-negative integer".to_string())?;
-            content_length = Some(parsed);
+        let mut encodings = head.headers.get_all("transfer-encoding");
+        let transfer_encoding = encodings.next();
+        if encodings.next().is_some() {
+            return Err("duplicate Transfer-Encoding header".to_string());
         }
 
         if let Some(te) = transfer_encoding {
@@ -182,29 +654,64 @@ impl Conn {
                 return Err("Transfer-Encoding header cannot be empty".to_string());
             }
 
-            if codings.iter().any(|c| *c != "chunked") {
+            if codings.iter().any(|c| !c.eq_ignore_ascii_case("chunked")) {
                 return Err("only chunked Transfer-Encoding is supported".to_string());
             }
 
             return Ok(BodyFraming::Chunked);
         }
 
+        let close = head.headers.get_all("connection").any(|value| {
+            value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("close"))
+        });
+        if content_length.is_none()
+            && options.read_body_until_close
+            && head.version == "HTTP/1.0"
+            && matches!(head.method, "POST" | "PUT" | "PATCH")
+            && close
+        {
+            return Ok(BodyFraming::UntilClose);
+        }
+
         Ok(BodyFraming::ContentLength(content_length.unwrap_or(0)))
     }
 
-    fn decode_chunked_body(raw: &[u8]) -> Result<Option<(Vec<u8>, usize)>, String> {
+    /// Decodes a complete chunked body, returning it with its trailers and the number of
+    /// bytes consumed. Fails with 413 as soon as a chunk size line pushes the body past
+    /// `max_body_bytes`, and with 431 for trailers over the head limits.
+    fn decode_chunked_body(
+        raw: &[u8],
+        options: &ParseOptions,
+    ) -> Result<Option<DecodedChunked>, (StatusCode, String)> {
+        let bad_request = |reason: &str| (StatusCode::BadRequest, reason.to_string());
+        let max_body = options.max_body_bytes;
+        // End of the CRLF-terminated line starting at `pos`, `None` while incomplete.
+        let find_line_end = |pos: usize| {
+            let rest = &raw[pos..];
+            if options.strict_line_endings {
+                let Some(lf) = rest.iter().position(|&b| b == b'\n') else {
+                    return Ok(None);
+                };
+                if find_bare_lf(&rest[..=lf], 0).is_some() {
+                    return Err(bad_request("chunked framing line ends with a bare LF"));
+                }
+                return Ok(Some(pos + lf - 1));
+            }
+            Ok(rest.windows(2).position(|w| w == b"\r\n").map(|i| pos + i))
+        };
         let mut pos = 0usize;
         let mut out = Vec::new();
 
         loop {
-            let Some(line_end_rel) = raw[pos..].windows(2).position(|w| w == b"\r\n") else {
+            let Some(line_end) = find_line_end(pos)? else {
                 return Ok(None);
             };
-            let line_end = pos + line_end_rel;
             let size_line = &raw[pos..line_end];
 
             let size_text = std::str::from_utf8(size_line)
-                .map_err(|_| "chunk size line is not valid UTF-8".to_string())?;
+                .map_err(|_| bad_request("chunk size line is not valid UTF-8"))?;
             let size_token = size_text
                 .split_once(';')
                 .map(|(n, _)| n)
@@ -212,11 +719,18 @@ impl Conn {
                 .trim();
 
             if size_token.is_empty() {
-                return Err("chunk size is missing".to_string());
+                return Err(bad_request("chunk size is missing"));
             }
 
             let chunk_size = usize::from_str_radix(size_token, 16)
-                .map_err(|_| "chunk size is not valid hexadecimal".to_string())?;
+                .map_err(|_| bad_request("chunk size is not valid hexadecimal"))?;
+
+            if max_body.is_some_and(|max| out.len().saturating_add(chunk_size) > max) {
+                return Err((
+                    StatusCode::PayloadTooLarge,
+                    "chunked body exceeds the configured maximum".to_string(),
+                ));
+            }
 
             pos = line_end + 2;
 
@@ -229,48 +743,386 @@ impl Conn {
                 pos += chunk_size;
 
                 if &raw[pos..pos + 2] != b"\r\n" {
-                    return Err("chunk data is not terminated with CRLF".to_string());
+                    return Err(bad_request("chunk data is not terminated with CRLF"));
                 }
                 pos += 2;
                 continue;
             }
 
+            // The trailer section shares the limits of the request head.
+            let trailer_start = pos;
+            let too_large = |end: usize, count: usize| {
+                options
+                    .max_header_bytes
+                    .is_some_and(|max| end - trailer_start > max)
+                    || options.max_header_count.is_some_and(|max| count > max)
+            };
+            let mut trailers = HeaderMap::default();
+            let mut count = 0usize;
             loop {
-                let Some(line_end_rel) = raw[pos..].windows(2).position(|w| w == b"\r\n") else {
+                let Some(line_end) = find_line_end(pos)? else {
+                    if too_large(raw.len(), count) {
+                        return Err(trailers_too_large());
+                    }
                     return Ok(None);
                 };
 
-                let line_end = pos + line_end_rel;
                 let line = &raw[pos..line_end];
                 pos = line_end + 2;
 
                 if line.is_empty() {
-                    return Ok(Some((out, pos)));
+                    return Ok(Some((out, trailers, pos)));
+                }
+                count += 1;
+                if too_large(pos, count) {
+                    return Err(trailers_too_large());
+                }
+
+                let text = std::str::from_utf8(line)
+                    .map_err(|_| bad_request("trailer line is not valid UTF-8"))?;
+                if text.starts_with([' ', '\t']) {
+                    return Err(bad_request("folded trailer lines are not accepted"));
+                }
+                let (name, value) = text
+                    .split_once(':')
+                    .ok_or_else(|| bad_request("trailer line has no colon"))?;
+                let name = name.trim();
+                if !FORBIDDEN_TRAILERS.contains(&name.to_ascii_lowercase().as_str()) {
+                    trailers.append(name, value);
                 }
             }
         }
     }
 }
 
+fn trailers_too_large() -> (StatusCode, String) {
+    (
+        StatusCode::RequestHeaderFieldsTooLarge,
+        "chunked trailers exceed the configured maximum".to_string(),
+    )
+}
+
+/// The client was promised `Content-Length` bytes the file no longer has.
+fn file_shrank() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being sent")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Conn;
+    use super::{Conn, ConnState, ParseOptions, find_bare_lf};
+    use crate::https::StatusCode;
+    use crate::router::ReadOutcome;
 
     #[test]
     fn decode_chunked_body_accepts_empty_trailers() {
         let raw = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
-        let decoded = Conn::decode_chunked_body(raw)
+        let decoded = Conn::decode_chunked_body(raw, &ParseOptions::default())
             .expect("chunked body should parse")
             .expect("chunked body should be complete");
 
         assert_eq!(decoded.0, b"hello world");
-        assert_eq!(decoded.1, raw.len());
+        assert_eq!(decoded.1.iter().count(), 0);
+        assert_eq!(decoded.2, raw.len());
+    }
+
+    #[test]
+    fn decode_chunked_body_returns_allowed_trailers() {
+        let raw = b"2\r\nhi\r\n0\r\nX-Checksum: abc\r\nX-Checksum: def\r\nContent-Length: 9\r\nHost: evil\r\n\r\n";
+        let (body, trailers, consumed) = Conn::decode_chunked_body(raw, &ParseOptions::default())
+            .expect("chunked body should parse")
+            .expect("chunked body should be complete");
+
+        assert_eq!(body, b"hi");
+        assert_eq!(consumed, raw.len());
+        assert_eq!(
+            trailers.get_all("x-checksum").collect::<Vec<_>>(),
+            ["abc", "def"]
+        );
+        assert_eq!(trailers.get("content-length"), None);
+        assert_eq!(trailers.get("host"), None);
+
+        let bad = Conn::decode_chunked_body(b"0\r\nno colon\r\n\r\n", &ParseOptions::default());
+        assert_eq!(bad.unwrap_err().0.code(), 400);
+
+        let options = ParseOptions {
+            max_header_count: Some(1),
+            ..ParseOptions::default()
+        };
+        let many = Conn::decode_chunked_body(b"0\r\nA: 1\r\nB: 2\r\n\r\n", &options);
+        assert_eq!(many.unwrap_err().0.code(), 431);
+
+        let options = ParseOptions {
+            max_header_bytes: Some(8),
+            ..ParseOptions::default()
+        };
+        let endless = Conn::decode_chunked_body(b"0\r\nX-Long: aaaaaaaa", &options);
+        assert_eq!(endless.unwrap_err().0.code(), 431);
     }
 
     #[test]
     fn decode_chunked_body_waits_for_final_crlf() {
         let raw = b"5\r\nhello\r\n0\r\n";
-        let decoded = Conn::decode_chunked_body(raw).expect("should not error");
+        let decoded =
+            Conn::decode_chunked_body(raw, &ParseOptions::default()).expect("should not error");
         assert!(decoded.is_none());
     }
+
+    #[test]
+    fn head_arriving_byte_by_byte_is_scanned_incrementally() {
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut conn = Conn::new(8080, ParseOptions::default());
+        for (i, byte) in head.iter().enumerate() {
+            let outcome = conn.read_outcome(&[*byte]);
+            if i + 1 < head.len() {
+                assert!(matches!(outcome, ReadOutcome::Pending), "byte {i}");
+                assert_eq!(conn.head_scanned, i + 1);
+            } else {
+                assert!(matches!(outcome, ReadOutcome::Ready(_)));
+                assert_eq!(conn.head_scanned, 0);
+            }
+        }
+
+        let mut conn = Conn::new(8080, ParseOptions::default());
+        assert!(matches!(
+            conn.read_outcome(b"GET / HTTP/1.1\r\nHost: x\r\n"),
+            ReadOutcome::Pending
+        ));
+        assert!(matches!(
+            conn.read_outcome(b"X-A: 1\n"),
+            ReadOutcome::Error {
+                status: StatusCode::BadRequest,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn bare_lf_line_endings_are_rejected_in_strict_mode() {
+        assert_eq!(find_bare_lf(b"a\r\nb\r\n", 0), None);
+        assert_eq!(find_bare_lf(b"a\r\nb\nc", 0), Some(4));
+        assert_eq!(find_bare_lf(b"\nx", 0), Some(0));
+        assert_eq!(find_bare_lf(b"a\r\nb", 2), None);
+        assert_eq!(find_bare_lf(b"a\nb\nc", 2), Some(3));
+
+        let rejected = |raw: &[u8]| {
+            matches!(
+                Conn::new(8080, ParseOptions::default()).read_outcome(raw),
+                ReadOutcome::Error {
+                    status: StatusCode::BadRequest,
+                    ..
+                }
+            )
+        };
+        assert!(rejected(b"GET / HTTP/1.1\nHost: x\n"));
+        assert!(rejected(b"GET / HTTP/1.1\r\nHost: x\n\r\n"));
+        assert!(rejected(
+            b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\nhi\r\n0\r\n\r\n"
+        ));
+        assert!(rejected(
+            b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\nX-Trailer: 1\n\r\n"
+        ));
+
+        // Chunk data itself may contain LFs.
+        let mut conn = Conn::new(8080, ParseOptions::default());
+        let outcome = conn.read_outcome(
+            b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n\n\n\r\n0\r\n\r\n",
+        );
+        let ReadOutcome::Ready(parts) = outcome else {
+            panic!("LFs inside chunk data are not line endings");
+        };
+        assert_eq!(parts.body_bytes, b"\n\n");
+
+        let lenient = ParseOptions {
+            strict_line_endings: false,
+            ..ParseOptions::default()
+        };
+        let mut conn = Conn::new(8080, lenient);
+        assert!(matches!(
+            conn.read_outcome(b"GET / HTTP/1.1\nHost: x\n"),
+            ReadOutcome::Pending
+        ));
+    }
+
+    #[test]
+    fn conflicting_framing_headers_are_rejected() {
+        let framing_error = |headers: &str| {
+            let raw = format!("POST / HTTP/1.1\r\nHost: x\r\n{headers}\r\nhello");
+            match Conn::new(8080, ParseOptions::default()).read_outcome(raw.as_bytes()) {
+                ReadOutcome::Error { status, reason } => Some((status.code(), reason)),
+                _ => None,
+            }
+        };
+
+        for headers in [
+            "Content-Length: 5\r\nContent-Length: 6\r\n",
+            "Content-Length: 5\r\nContent-Length: 5\r\n",
+            "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+            "Content-Length: +5\r\n",
+            "Content-Length: 5, 5\r\n",
+            "Content-Length : 5\r\n",
+            "Transfer-Encoding: gzip, chunked\r\n",
+        ] {
+            let (code, _) =
+                framing_error(headers).unwrap_or_else(|| panic!("accepted {headers:?}"));
+            assert_eq!(code, 400, "{headers:?}");
+        }
+
+        assert!(framing_error("Content-Length: 5\r\n").is_none());
+        assert!(framing_error("content-length:5\r\n").is_none());
+    }
+
+    #[test]
+    fn ready_request_leaves_only_the_next_one_in_in_buf() {
+        let second = b"GET /next HTTP/1.1\r\nHost: x\r\n\r\n";
+        for first in [
+            &b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello"[..],
+            b"POST /a HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        ] {
+            let mut conn = Conn::new(8080, ParseOptions::default());
+            let ReadOutcome::Ready(parts) = conn.read_outcome(&[first, second].concat()) else {
+                panic!("first request should be complete");
+            };
+            assert!(parts.header_bytes.starts_with(b"POST /a "));
+            assert!(parts.header_bytes.ends_with(b"\r\n\r\n"));
+            assert_eq!(parts.body_bytes, b"hello");
+            assert_eq!(conn.in_buf, second);
+
+            conn.state = ConnState::ReadingHeaders;
+            let ReadOutcome::Ready(parts) = conn.read_outcome(&[]) else {
+                panic!("second request should survive in in_buf");
+            };
+            assert_eq!(parts.header_bytes, second);
+            assert!(conn.in_buf.is_empty());
+        }
+    }
+
+    fn until_close() -> ParseOptions {
+        ParseOptions {
+            read_body_until_close: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn http10_post_without_length_reads_body_until_eof() {
+        let mut conn = Conn::new(8080, until_close());
+        let outcome = conn.read_outcome(b"POST /upload HTTP/1.0\r\nConnection: close\r\n\r\nhel");
+        assert!(matches!(outcome, ReadOutcome::Pending));
+        assert!(matches!(conn.read_outcome(b"lo"), ReadOutcome::Pending));
+
+        let Some(ReadOutcome::Ready(parts)) = conn.finish_at_eof() else {
+            panic!("EOF should complete the request");
+        };
+        assert_eq!(parts.body_bytes, b"hello");
+        assert!(conn.in_buf.is_empty());
+    }
+
+    #[test]
+    fn read_until_close_needs_the_option_and_connection_close() {
+        for (options, raw) in [
+            (
+                ParseOptions::default(),
+                &b"POST /upload HTTP/1.0\r\nConnection: close\r\n\r\nhello"[..],
+            ),
+            (until_close(), b"POST /upload HTTP/1.0\r\n\r\nhello"),
+        ] {
+            let mut conn = Conn::new(8080, options);
+            let ReadOutcome::Ready(parts) = conn.read_outcome(raw) else {
+                panic!("request without framing should complete immediately");
+            };
+            assert!(parts.body_bytes.is_empty());
+            assert!(conn.finish_at_eof().is_none());
+        }
+    }
+
+    #[test]
+    fn continue_is_queued_only_for_http11_clients_that_expect_it() {
+        for (raw, queued) in [
+            (&b"POST /a HTTP/1.1\r\nHost: x\r\nexpect: 100-Continue\r\nContent-Length: 5\r\n\r\n"[..], true),
+            (b"POST /a HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n", false),
+            (b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\n", false),
+        ] {
+            let mut conn = Conn::new(8080, ParseOptions::default());
+            assert!(matches!(conn.read_outcome(raw), ReadOutcome::Pending));
+            assert_eq!(conn.out_buf.starts_with(b"HTTP/1.1 100 Continue\r\n\r\n"), queued);
+        }
+    }
+
+    fn limited(max: usize) -> Conn {
+        let options = ParseOptions {
+            max_body_bytes: Some(max),
+            ..ParseOptions::default()
+        };
+        Conn::new(8080, options)
+    }
+
+    #[test]
+    fn oversized_request_head_is_rejected_before_its_terminator() {
+        let options = ParseOptions {
+            max_header_bytes: Some(64),
+            ..ParseOptions::default()
+        };
+        let mut conn = Conn::new(8080, options);
+        assert!(matches!(
+            conn.read_outcome(b"GET / HTTP/1.1\r\nHost: x\r\n"),
+            ReadOutcome::Pending
+        ));
+        let outcome = conn.read_outcome(&[b'a'; 64]);
+        assert!(matches!(
+            outcome,
+            ReadOutcome::Error {
+                status: StatusCode::RequestHeaderFieldsTooLarge,
+                ..
+            }
+        ));
+
+        let mut conn = Conn::new(8080, options);
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert!(matches!(conn.read_outcome(head), ReadOutcome::Ready(_)));
+    }
+
+    #[test]
+    fn advertised_content_length_over_limit_is_rejected_before_buffering() {
+        let mut conn = limited(4);
+        let outcome = conn.read_outcome(b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+        assert!(matches!(
+            outcome,
+            ReadOutcome::Error {
+                status: StatusCode::PayloadTooLarge,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn chunked_and_until_close_bodies_over_limit_are_rejected() {
+        let mut conn = limited(4);
+        conn.read_outcome(
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n",
+        );
+        let outcome = conn.read_outcome(b"2\r\n");
+        assert!(matches!(
+            outcome,
+            ReadOutcome::Error {
+                status: StatusCode::PayloadTooLarge,
+                ..
+            }
+        ));
+
+        let options = ParseOptions {
+            max_body_bytes: Some(4),
+            ..until_close()
+        };
+        let mut conn = Conn::new(8080, options);
+        let outcome = conn.read_outcome(b"POST /upload HTTP/1.0\r\nConnection: close\r\n\r\nabcd");
+        assert!(matches!(outcome, ReadOutcome::Pending));
+        assert!(matches!(
+            conn.read_outcome(b"e"),
+            ReadOutcome::Error {
+                status: StatusCode::PayloadTooLarge,
+                ..
+            }
+        ));
+    }
 }
@@ -0,0 +1,304 @@
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::https::{Body, Request, Response, StatusCode, html_response, response_with_body};
+use crate::router::Data;
+use crate::utils::httpdate;
+
+use super::conditional::{etag_for, if_match_matches, if_none_match_matches, not_modified};
+use super::error_response;
+
+/// Serves `bytes` from memory. The ETag is computed once here, and every response
+/// shares the same `Arc` instead of copying the asset.
+pub fn static_asset_handler(
+    bytes: Arc<[u8]>,
+    content_type: &str,
+) -> impl Fn(&Request, &Data) -> Response + Send + Sync + 'static {
+    let etag = etag_for(&bytes);
+    let content_type = content_type.to_string();
+
+    move |req: &Request, _data: &Data| -> Response {
+        if req
+            .headers
+            .get("if-match")
+            .is_some_and(|v| !if_match_matches(v, &etag))
+        {
+            return error_response(&req.version, StatusCode::PreconditionFailed);
+        }
+
+        if req
+            .headers
+            .get("if-none-match")
+            .is_some_and(|v| if_none_match_matches(v, &etag))
+        {
+            return not_modified(&req.version, &etag);
+        }
+
+        let mut resp = response_with_body(
+            &req.version,
+            StatusCode::Ok,
+            &content_type,
+            Body::Shared(bytes.clone()),
+        );
+        resp.headers.insert("ETag", &etag);
+        resp
+    }
+}
+
+/// Serves files under `root`, taking the relative path from the `path` capture of a
+/// `*path` route. Paths that climb out of `root`, directly or through a symlink, get
+/// 403 and missing files get 404. A directory is answered with its `index.html`, or
+/// else with a generated listing while `listing` is set and 403 otherwise. A
+/// `?download` query asks the client to save the file instead of displaying it.
+///
+/// File bodies are sent with `sendfile` rather than read into memory.
+pub fn static_dir_handler(
+    root: impl Into<PathBuf>,
+    listing: Arc<AtomicBool>,
+) -> impl Fn(&Request, &Data) -> Response + Send + Sync + 'static {
+    let root = root.into();
+
+    move |req: &Request, data: &Data| -> Response {
+        let relative = data.path_value.get("path").map_or("", String::as_str);
+        let Some(path) = resolve_under(&root, relative) else {
+            return error_response(&req.version, StatusCode::Forbidden);
+        };
+
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => {
+                let index = resolve_under(&root, &format!("{relative}/index.html"))
+                    .filter(|index| index.is_file());
+                match index {
+                    Some(index) => serve_file(req, &index),
+                    None if listing.load(Ordering::Relaxed) => directory_listing(req, &path),
+                    None => error_response(&req.version, StatusCode::Forbidden),
+                }
+            }
+            Ok(_) => {
+                let mut resp = serve_file(req, &path);
+                if data.query_value.contains_key("download")
+                    && resp.status.code() == 200
+                    && let Some(name) = path.file_name()
+                {
+                    resp.attachment(&name.to_string_lossy());
+                }
+                resp
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                error_response(&req.version, StatusCode::NotFound)
+            }
+            Err(e) => {
+                crate::error!("could not stat static file", "path" => path.display(), "error" => e);
+                error_response(&req.version, StatusCode::InternalServerError)
+            }
+        }
+    }
+}
+
+fn serve_file(req: &Request, path: &Path) -> Response {
+    let opened = File::open(path).and_then(|file| {
+        Response::file(
+            &req.version,
+            StatusCode::Ok,
+            content_type_for_path(path),
+            file,
+        )
+    });
+    match opened {
+        Ok(resp) => resp,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            error_response(&req.version, StatusCode::NotFound)
+        }
+        Err(e) => {
+            crate::error!("could not read static file", "path" => path.display(), "error" => e);
+            error_response(&req.version, StatusCode::InternalServerError)
+        }
+    }
+}
+
+/// HTML page linking to each entry of `dir`, with sizes and modification times.
+fn directory_listing(req: &Request, dir: &Path) -> Response {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            crate::error!("could not list directory", "path" => dir.display(), "error" => e);
+            return error_response(&req.version, StatusCode::InternalServerError);
+        }
+    };
+    let mut rows: Vec<(String, String, String)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            let size = if meta.is_dir() {
+                name.push('/');
+                "-".to_string()
+            } else {
+                meta.len().to_string()
+            };
+            let modified = meta.modified().map_or("-".to_string(), httpdate::format);
+            Some((name, size, modified))
+        })
+        .collect();
+    rows.sort();
+
+    let base = req.path.trim_end_matches('/');
+    let title = escape_html(&format!("{base}/"));
+    let mut body = format!(
+        "<html><head><title>Index of {title}</title></head><body><h1>Index of {title}</h1><table>"
+    );
+    for (name, size, modified) in rows {
+        // `base` came off the wire already encoded; the entry name is still raw.
+        let href = match name.strip_suffix('/') {
+            Some(dir) => format!("{base}/{}/", encode_segment(dir)),
+            None => format!("{base}/{}", encode_segment(&name)),
+        };
+        body.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            escape_html(&href),
+            escape_html(&name)
+        ));
+    }
+    body.push_str("</table></body></html>");
+
+    html_response(&req.version, StatusCode::Ok, &body)
+}
+
+/// Percent-encodes `segment` for use as one path segment of a URL, leaving only the
+/// characters RFC 3986 allows there unescaped.
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Joins `relative` onto `root`, or `None` when the result would escape `root`.
+/// Files that do not exist yet resolve to the joined path so the caller can 404.
+fn resolve_under(root: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+
+    let joined = root.join(relative);
+    match (root.canonicalize(), joined.canonicalize()) {
+        (Ok(root), Ok(target)) if target.starts_with(&root) => Some(target),
+        (Ok(_), Ok(_)) => None,
+        _ => Some(joined),
+    }
+}
+
+/// `Content-Type` by file extension, for `content_type_for_path`. Extensions are
+/// lowercase; add new ones here.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// The `Content-Type` for a file at `path`, going by its extension in any case, or
+/// `application/octet-stream` when the extension is missing or unknown.
+pub fn content_type_for_path(path: impl AsRef<Path>) -> &'static str {
+    let extension = path.as_ref().extension().and_then(|e| e.to_str());
+    extension
+        .and_then(|extension| {
+            CONTENT_TYPES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        })
+        .map_or("application/octet-stream", |&(_, content_type)| {
+            content_type
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type_for_path, encode_segment};
+
+    #[test]
+    fn content_types_follow_the_extension() {
+        assert_eq!(
+            content_type_for_path("index.HTML"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            content_type_for_path("/static/app.min.js"),
+            "application/javascript"
+        );
+        assert_eq!(content_type_for_path("pkg/module.wasm"), "application/wasm");
+        assert_eq!(content_type_for_path("photo.jpeg"), "image/jpeg");
+        assert_eq!(content_type_for_path("archive.tar.gz"), "application/gzip");
+        assert_eq!(
+            content_type_for_path("Makefile"),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type_for_path("data.unknown"),
+            "application/octet-stream"
+        );
+        assert_eq!(content_type_for_path(".png"), "application/octet-stream");
+    }
+
+    #[test]
+    fn segments_are_percent_encoded() {
+        assert_eq!(encode_segment("a b%#.txt"), "a%20b%25%23.txt");
+        assert_eq!(encode_segment("a&b?c/d"), "a&b%3Fc%2Fd");
+        assert_eq!(encode_segment("naïve~1"), "na%C3%AFve~1");
+    }
+}
@@ -0,0 +1,105 @@
+use crate::https::{Request, Response, StatusCode};
+use crate::router::{Data, Next};
+
+use super::error_response;
+
+/// User name and password from an `Authorization: Basic` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// The credentials of an `Authorization: Basic <base64>` header (RFC 7617), or `None`
+/// when the header is missing, uses another scheme or does not decode to UTF-8
+/// `user:password`.
+pub fn basic_credentials(req: &Request) -> Option<Credentials> {
+    let value = req.headers.get("authorization")?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(decode_base64(token.trim())?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some(Credentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Middleware that lets a request through only when `check` accepts its Basic
+/// credentials, and otherwise answers `401` with a challenge for `realm`. Install it
+/// with `Router::add_middleware` or `Router::add_route_middleware`.
+pub fn basic_auth<F>(
+    realm: &str,
+    check: F,
+) -> impl Fn(&Request, &Data, Next<'_>) -> Response + Send + Sync + 'static
+where
+    F: Fn(&Credentials) -> bool + Send + Sync + 'static,
+{
+    let escaped = realm.replace('\\', "\\\\").replace('"', "\\\"");
+    let challenge = format!("Basic realm=\"{escaped}\", charset=\"UTF-8\"");
+    move |req, data, next| match basic_credentials(req) {
+        Some(credentials) if check(&credentials) => next.run(req, data),
+        _ => {
+            let mut resp = error_response(&req.version, StatusCode::Unauthorized);
+            resp.headers.insert("WWW-Authenticate", &challenge);
+            resp
+        }
+    }
+}
+
+/// Decodes standard, padded base64. Unpadded input is accepted too, since some
+/// clients leave the padding off.
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let bytes = input.as_bytes();
+    let unpadded = bytes
+        .strip_suffix(b"==")
+        .or_else(|| bytes.strip_suffix(b"="));
+    let data = unpadded.unwrap_or(bytes);
+    if unpadded.is_some() && !bytes.len().is_multiple_of(4) || data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for group in data.chunks(4) {
+        let mut acc = 0u32;
+        for &c in group {
+            acc = (acc << 6) | value(c)?;
+        }
+        acc <<= 6 * (4 - group.len() as u32);
+        out.extend_from_slice(&acc.to_be_bytes()[1..group.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_base64;
+
+    #[test]
+    fn decodes_base64_with_and_without_padding() {
+        assert_eq!(
+            decode_base64("YWxhZGRpbjpvcGVuc2VzYW1l").unwrap(),
+            b"aladdin:opensesame"
+        );
+        assert_eq!(decode_base64("YQ==").unwrap(), b"a");
+        assert_eq!(decode_base64("YWI=").unwrap(), b"ab");
+        assert_eq!(decode_base64("YWI").unwrap(), b"ab");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert!(decode_base64("Y").is_none());
+        assert!(decode_base64("YW=").is_none());
+        assert!(decode_base64("Y!==").is_none());
+    }
+}
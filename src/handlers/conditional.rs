@@ -0,0 +1,90 @@
+use crate::https::{HeaderMap, Response, StatusCode};
+
+/// Strong validator derived from the bytes (64-bit FNV-1a, quoted as the spec requires).
+pub fn etag_for(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("\"{hash:016x}\"")
+}
+
+fn opaque_tag(etag: &str) -> &str {
+    etag.trim().strip_prefix("W/").unwrap_or(etag.trim())
+}
+
+fn is_weak(etag: &str) -> bool {
+    etag.trim().starts_with("W/")
+}
+
+/// Strong comparison (RFC 7232 2.3.2): both tags must be strong and identical.
+pub fn strong_eq(a: &str, b: &str) -> bool {
+    !is_weak(a) && !is_weak(b) && opaque_tag(a) == opaque_tag(b)
+}
+
+/// Weak comparison (RFC 7232 2.3.2): the opaque tags match, `W/` prefixes ignored.
+pub fn weak_eq(a: &str, b: &str) -> bool {
+    opaque_tag(a) == opaque_tag(b)
+}
+
+/// True when an `If-None-Match` value lists `etag` (or is `*`). Uses weak comparison,
+/// as required for `If-None-Match` on GET/HEAD.
+pub fn if_none_match_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || weak_eq(candidate, etag))
+}
+
+/// True when an `If-Match` value lists `etag` (or is `*`). Uses strong comparison,
+/// so a weak validator never satisfies `If-Match`.
+pub fn if_match_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || strong_eq(candidate, etag))
+}
+
+/// `304 Not Modified` carrying the validator but no body or `Content-Length`.
+pub fn not_modified(version: &str, etag: &str) -> Response {
+    let mut headers = HeaderMap::default();
+    headers.insert("ETag", etag);
+
+    Response {
+        version: version.to_string(),
+        status: StatusCode::NotModified,
+        headers,
+        body: Default::default(),
+        stream: None,
+        raw: None,
+        file: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{if_match_matches, if_none_match_matches, strong_eq, weak_eq};
+
+    #[test]
+    fn weak_comparison_ignores_weakness_indicator() {
+        assert!(weak_eq("W/\"abc\"", "\"abc\""));
+        assert!(weak_eq("W/\"abc\"", "W/\"abc\""));
+        assert!(!weak_eq("W/\"abc\"", "\"abd\""));
+    }
+
+    #[test]
+    fn strong_comparison_rejects_weak_tags() {
+        assert!(!strong_eq("W/\"abc\"", "\"abc\""));
+        assert!(!strong_eq("W/\"abc\"", "W/\"abc\""));
+        assert!(strong_eq("\"abc\"", "\"abc\""));
+    }
+
+    #[test]
+    fn headers_use_the_comparison_their_spec_requires() {
+        assert!(if_none_match_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(!if_match_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(if_match_matches("\"abc\"", "\"abc\""));
+        assert!(if_match_matches("*", "\"abc\""));
+    }
+}
@@ -1,19 +1,27 @@
+pub mod assets;
+pub mod auth;
+pub mod conditional;
+
 use std::fs;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
-use crate::https::{HttpMethod, Request, Response, StatusCode, response_with_body};
+use crate::https::{
+    HttpMethod, Request, Response, StatusCode, html_response, response_with_body, text_response,
+};
 use crate::router::{Data, Router};
 
+/// Small HTML page for `status`. It carries no `Connection` header: the event loop
+/// keeps the connection open after errors on requests it could parse and closes it
+/// after framing errors, where the next request's start is unknown.
 pub fn error_response(version: &str, status: StatusCode) -> Response {
     let reason = status.reason();
     let body = format!(
         "<html><body><h1>{} {}</h1></body></html>",
         status.code(),
         reason
-    )
-    .into_bytes();
-    response_with_body(version, status, "text/html; charset=utf-8", body)
+    );
+    html_response(version, status, &body)
 }
 
 pub fn register_routes(router: &mut Router) {
@@ -51,45 +59,25 @@ fn handle_public_root(req: &Request, data: &Data) -> Response {
         "<html><body><h1>Public</h1><p>Host: {host}</p><p>Port: 8080</p><p>Session: {sid} ({session_kind})</p></body></html>"
     );
 
-    response_with_body(
-        &req.version,
-        StatusCode::Ok,
-        "text/html; charset=utf-8",
-        body.into_bytes(),
-    )
+    html_response(&req.version, StatusCode::Ok, &body)
 }
 
 fn handle_public_health(req: &Request, _data: &Data) -> Response {
     let _ = req.data.body.len();
 
-    response_with_body(
-        &req.version,
-        StatusCode::Ok,
-        "text/plain; charset=utf-8",
-        b"PUBLIC_OK".to_vec(),
-    )
+    text_response(&req.version, StatusCode::Ok, "PUBLIC_OK")
 }
 
 fn handle_admin_root(req: &Request, _data: &Data) -> Response {
     let body = "<html><body><h1>Admin</h1><p>Port: 9090</p></body></html>";
 
-    response_with_body(
-        &req.version,
-        StatusCode::Ok,
-        "text/html; charset=utf-8",
-        body.as_bytes().to_vec(),
-    )
+    html_response(&req.version, StatusCode::Ok, body)
 }
 
 fn handle_admin_health(req: &Request, _data: &Data) -> Response {
     let _ = req.data.body.len();
 
-    response_with_body(
-        &req.version,
-        StatusCode::Ok,
-        "text/plain; charset=utf-8",
-        b"ADMIN_OK".to_vec(),
-    )
+    text_response(&req.version, StatusCode::Ok, "ADMIN_OK")
 }
 
 fn handle_upload(req: &Request, _data: &Data) -> Response {
@@ -101,20 +89,14 @@ fn handle_upload(req: &Request, _data: &Data) -> Response {
 
     if let Err(e) = fs::write("uploaded", &req.data.body) {
         eprintln!("failed to save uploaded body: {e}");
-        return response_with_body(
+        return text_response(
             &req.version,
             StatusCode::InternalServerError,
-            "text/plain; charset=utf-8",
-            b"failed to save upload".to_vec(),
+            "failed to save upload",
         );
     }
 
-    response_with_body(
-        &req.version,
-        StatusCode::Ok,
-        "text/plain; charset=utf-8",
-        b"ok".to_vec(),
-    )
+    text_response(&req.version, StatusCode::Ok, "ok")
 }
 
 fn file_server_factory(
@@ -142,23 +124,13 @@ fn file_server_factory(
 
 fn handle_file_by_name(req: &Request, data: &Data) -> Response {
     let Some(name) = data.path_value.get("name") else {
-        return response_with_body(
-            &req.version,
-            StatusCode::BadRequest,
-            "text/plain; charset=utf-8",
-            b"missing file name".to_vec(),
-        );
+        return text_response(&req.version, StatusCode::BadRequest, "missing file name");
     };
 
     let path = match file_path_from_name(name) {
         Ok(path) => path,
         Err(msg) => {
-            return response_with_body(
-                &req.version,
-                StatusCode::BadRequest,
-                "text/plain; charset=utf-8",
-                msg.into_bytes(),
-            );
+            return text_response(&req.version, StatusCode::BadRequest, &msg);
         }
     };
 
@@ -178,17 +150,13 @@ fn handle_file_get(req: &Request, path: &Path) -> Response {
             "application/octet-stream",
             bytes,
         ),
-        Err(e) if e.kind() == ErrorKind::NotFound => response_with_body(
-            &req.version,
-            StatusCode::NotFound,
-            "text/plain; charset=utf-8",
-            b"file not found".to_vec(),
-        ),
-        Err(_) => response_with_body(
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            text_response(&req.version, StatusCode::NotFound, "file not found")
+        }
+        Err(_) => text_response(
             &req.version,
             StatusCode::InternalServerError,
-            "text/plain; charset=utf-8",
-            b"failed to read file".to_vec(),
+            "failed to read file",
         ),
     }
 }
@@ -196,11 +164,10 @@ fn handle_file_get(req: &Request, path: &Path) -> Response {
 fn handle_file_post(req: &Request, path: &Path) -> Response {
     if let Err(e) = fs::create_dir_all("data") {
         eprintln!("failed to create data dir: {e}");
-        return response_with_body(
+        return text_response(
             &req.version,
             StatusCode::InternalServerError,
-            "text/plain; charset=utf-8",
-            b"failed to prepare storage".to_vec(),
+            "failed to prepare storage",
         );
     }
 
@@ -212,18 +179,12 @@ fn handle_file_post(req: &Request, path: &Path) -> Response {
             } else {
                 StatusCode::Created
             };
-            response_with_body(
-                &req.version,
-                status,
-                "text/plain; charset=utf-8",
-                b"file saved".to_vec(),
-            )
+            text_response(&req.version, status, "file saved")
         }
-        Err(_) => response_with_body(
+        Err(_) => text_response(
             &req.version,
             StatusCode::InternalServerError,
-            "text/plain; charset=utf-8",
-            b"failed to save file".to_vec(),
+            "failed to save file",
         ),
     }
 }
@@ -236,17 +197,13 @@ fn handle_file_delete(req: &Request, path: &Path) -> Response {
             "text/plain; charset=utf-8",
             Vec::new(),
         ),
-        Err(e) if e.kind() == ErrorKind::NotFound => response_with_body(
-            &req.version,
-            StatusCode::NotFound,
-            "text/plain; charset=utf-8",
-            b"file not found".to_vec(),
-        ),
-        Err(_) => response_with_body(
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            text_response(&req.version, StatusCode::NotFound, "file not found")
+        }
+        Err(_) => text_response(
             &req.version,
             StatusCode::InternalServerError,
-            "text/plain; charset=utf-8",
-            b"failed to delete file".to_vec(),
+            "failed to delete file",
         ),
     }
 }
@@ -1,92 +1,384 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::sync::Arc;
 
-use crate::router::Data;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::multipart::{DEFAULT_MAX_PARTS, MultipartError, Part, parse_multipart};
+use crate::router::{Data, parse_query};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HttpMethod {
     Get,
+    Head,
     Post,
+    Put,
+    Patch,
     Delete,
+    Options,
     Unknown(String),
 }
 
 impl HttpMethod {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "GET" => HttpMethod::Get,
+            "HEAD" => HttpMethod::Head,
             "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "PATCH" => HttpMethod::Patch,
             "DELETE" => HttpMethod::Delete,
+            "OPTIONS" => HttpMethod::Options,
             other => HttpMethod::Unknown(other.to_string()),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Unknown(name) => name,
+        }
+    }
+
+    /// TRACE and OPTIONS are the only methods `Max-Forwards` applies to.
+    pub fn honors_max_forwards(&self) -> bool {
+        match self {
+            HttpMethod::Options => true,
+            HttpMethod::Unknown(name) => name == "TRACE",
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum StatusCode {
+    SwitchingProtocols,
     Ok,
     Created,
     NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
     BadRequest,
+    Unauthorized,
     Forbidden,
     NotFound,
     MethodNotAllowed,
+    RequestTimeout,
+    PreconditionFailed,
     PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    ServiceUnavailable,
+    GatewayTimeout,
     VersionNotSupported,
 }
 
 impl StatusCode {
     pub fn code(self) -> u16 {
         match self {
+            StatusCode::SwitchingProtocols => 101,
             StatusCode::Ok => 200,
             StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
             StatusCode::Created => 201,
             StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::SeeOther => 303,
+            StatusCode::NotModified => 304,
             StatusCode::Forbidden => 403,
             StatusCode::NotFound => 404,
             StatusCode::MethodNotAllowed => 405,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::PreconditionFailed => 412,
             StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::TooManyRequests => 429,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
             StatusCode::InternalServerError => 500,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::GatewayTimeout => 504,
             StatusCode::VersionNotSupported => 505,
         }
     }
 
+    /// 1xx, 204 and 304 responses never carry a body.
+    pub fn allows_body(self) -> bool {
+        let code = self.code();
+        !(100..200).contains(&code) && code != 204 && code != 304
+    }
+
     pub fn reason(self) -> String {
         match self {
+            StatusCode::SwitchingProtocols => "Switching Protocols",
             StatusCode::Ok => "OK",
             StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
             StatusCode::Forbidden => "Forbidden",
             StatusCode::Created => "Created",
             StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
             StatusCode::NotFound => "Not Found",
             StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::PreconditionFailed => "Precondition Failed",
             StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
             StatusCode::VersionNotSupported => "HTTP Version Not Supported",
         }
         .to_string()
     }
 }
 
+/// Header fields keyed by lowercased name. A name can hold several values, kept in the
+/// order they were added; they go on the wire as one line each.
 #[derive(Debug, Default, Clone)]
 pub struct HeaderMap {
-    headers: HashMap<String, String>,
+    headers: HashMap<String, Vec<String>>,
 }
 
 impl HeaderMap {
+    /// Sets `name` to `value`, replacing any values it had.
     pub fn insert(&mut self, name: &str, value: &str) {
         self.headers
-            .insert(name.to_ascii_lowercase(), value.trim().to_string());
+            .insert(name.to_ascii_lowercase(), vec![value.trim().to_string()]);
     }
 
+    /// Adds `value` after any values `name` already has, e.g. a second `Set-Cookie`.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.headers
+            .entry(name.to_ascii_lowercase())
+            .or_default()
+            .push(value.trim().to_string());
+    }
+
+    /// The first value of `name`.
     pub fn get(&self, name: &str) -> Option<&str> {
         self.headers
             .get(&name.to_ascii_lowercase())
+            .and_then(|values| values.first())
             .map(|s| s.as_str())
     }
 
+    /// Every value of `name`, in the order they were added.
+    pub fn get_all(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Each `(name, value)` pair, with a multi-valued name appearing once per value.
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
-        self.headers.iter()
+        self.headers
+            .iter()
+            .flat_map(|(name, values)| values.iter().map(move |value| (name, value)))
+    }
+
+    /// Removes every value of `name`, returning the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.headers
+            .remove(&name.to_ascii_lowercase())
+            .and_then(|values| values.into_iter().next())
+    }
+}
+
+/// Parses an `Accept` header into `(media_type, q)` pairs, most preferred first.
+///
+/// Higher q-values win; on ties the more specific range (`text/html` over `text/*`
+/// over `*/*`) comes first, then header order. Ranges with `q=0` are dropped since
+/// they mark a type as unacceptable. A missing or malformed q counts as 1.0.
+pub fn parse_accept(header: &str) -> Vec<(String, f32)> {
+    let mut ranges: Vec<(String, f32)> = Vec::new();
+
+    for entry in header.split(',') {
+        let mut params = entry.split(';');
+        let media_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        if media_type.is_empty() || !media_type.contains('/') {
+            continue;
+        }
+
+        let mut q = 1.0f32;
+        for param in params {
+            let Some((name, value)) = param.split_once('=') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("q") {
+                q = value.trim().parse::<f32>().unwrap_or(1.0).clamp(0.0, 1.0);
+            }
+        }
+
+        if q > 0.0 {
+            ranges.push((media_type, q));
+        }
+    }
+
+    let specificity = |media_type: &str| match media_type {
+        "*/*" => 0,
+        t if t.ends_with("/*") => 1,
+        _ => 2,
+    };
+    ranges.sort_by(|a, b| {
+        b.1.total_cmp(&a.1)
+            .then_with(|| specificity(&b.0).cmp(&specificity(&a.0)))
+    });
+    ranges
+}
+
+/// Parsed `Content-Type` value: `type/subtype` plus lowercase-named parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub media_type: String,
+    pub subtype: String,
+    pub parameters: HashMap<String, String>,
+}
+
+impl ContentType {
+    /// Parses e.g. `multipart/form-data; boundary="a;b"`. Type, subtype and parameter
+    /// names are lowercased; quoted parameter values are unquoted and unescaped.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (essence, rest) = match value.find(';') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => (value, ""),
+        };
+        let (media_type, subtype) = essence.trim().split_once('/')?;
+        let (media_type, subtype) = (media_type.trim(), subtype.trim());
+        if media_type.is_empty() || subtype.is_empty() {
+            return None;
+        }
+
+        let parameters = parse_parameters(rest);
+        Some(Self {
+            media_type: media_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters,
+        })
+    }
+
+    /// `type/subtype` without parameters, e.g. `application/json`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.media_type, self.subtype)
+    }
+
+    /// Whether the type is text a client reads line by line, as opposed to binary data.
+    pub fn is_textual(&self) -> bool {
+        self.media_type == "text"
+            || self.subtype.ends_with("+json")
+            || self.subtype.ends_with("+xml")
+            || matches!(
+                self.essence().as_str(),
+                "application/json" | "application/xml" | "application/javascript"
+            )
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .get(&name.to_ascii_lowercase())
+            .map(|v| v.as_str())
+    }
+}
+
+/// Parses `; name=value` parameters as found after a media type or a
+/// `Content-Disposition` type. Names are lowercased; quoted values are unquoted and
+/// unescaped.
+pub(crate) fn parse_parameters(mut rest: &str) -> HashMap<String, String> {
+    let mut parameters = HashMap::new();
+    while !rest.is_empty() {
+        let (name, after_name) = match rest.find('=') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => break,
+        };
+        let name = name.trim().trim_start_matches(';').trim();
+        let after_name = after_name.trim_start();
+
+        let (param_value, remaining) = match after_name.strip_prefix('"') {
+            Some(quoted) => {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                unquoted.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        other => unquoted.push(other),
+                    }
+                }
+                let remaining = &quoted[end..];
+                let remaining = remaining.find(';').map_or("", |i| &remaining[i + 1..]);
+                (unquoted, remaining)
+            }
+            None => match after_name.find(';') {
+                Some(i) => (after_name[..i].trim().to_string(), &after_name[i + 1..]),
+                None => (after_name.trim().to_string(), ""),
+            },
+        };
+
+        if !name.is_empty() {
+            parameters.insert(name.to_ascii_lowercase(), param_value);
+        }
+        rest = remaining;
+    }
+    parameters
+}
+
+/// Why a text body could not be decoded to UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CharsetError {
+    /// The declared charset is not one `decode_text` knows.
+    Unsupported(String),
+    /// The bytes are not valid in the declared charset.
+    Malformed,
+}
+
+/// Decodes `bytes` written in `charset` (UTF-8 when none is declared). Supports UTF-8,
+/// US-ASCII and ISO-8859-1.
+pub fn decode_text(bytes: &[u8], charset: Option<&str>) -> Result<String, CharsetError> {
+    let charset = charset.unwrap_or("utf-8").trim().to_ascii_lowercase();
+    match charset.as_str() {
+        "utf-8" | "utf8" => String::from_utf8(bytes.to_vec()).map_err(|_| CharsetError::Malformed),
+        "us-ascii" | "ascii" if bytes.is_ascii() => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        "us-ascii" | "ascii" => Err(CharsetError::Malformed),
+        // Latin-1 maps every byte to the code point of the same value.
+        "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+            Ok(bytes.iter().map(|&b| char::from(b)).collect())
+        }
+        _ => Err(CharsetError::Unsupported(charset)),
     }
 }
 
@@ -97,19 +389,698 @@ pub struct Request {
     pub query: String,
     pub version: String,
     pub headers: HeaderMap,
+    /// Fields sent after a chunked body, kept apart from `headers` because the client
+    /// could not know them up front. Fields that affect framing, routing or request
+    /// handling (`Content-Length`, `Host`, `Authorization`, ...) are dropped.
+    pub trailers: HeaderMap,
     pub data: Data,
 }
 
+impl Request {
+    /// Remaining hop count from `Max-Forwards`, which only means something on TRACE and
+    /// OPTIONS. An origin server like this one always answers such requests itself.
+    pub fn max_forwards(&self) -> Option<u32> {
+        if !self.method.honors_max_forwards() {
+            return None;
+        }
+        self.headers.get("max-forwards")?.parse().ok()
+    }
+
+    /// Fields of an `application/x-www-form-urlencoded` body, keeping the first value of
+    /// repeated keys; `None` for any other content type. Decoded like the query string.
+    pub fn form(&self) -> Option<HashMap<String, String>> {
+        let fields = self.form_all()?;
+        Some(
+            fields
+                .into_iter()
+                .map(|(key, mut values)| (key, values.swap_remove(0)))
+                .collect(),
+        )
+    }
+
+    /// Every value of every form field, in order; see `form`.
+    pub fn form_all(&self) -> Option<HashMap<String, Vec<String>>> {
+        let ct = ContentType::parse(self.headers.get("content-type")?)?;
+        if ct.media_type != "application" || ct.subtype != "x-www-form-urlencoded" {
+            return None;
+        }
+        Some(parse_query(&String::from_utf8_lossy(&self.data.body)))
+    }
+
+    /// Parts of a `multipart/form-data` (or other `multipart/*`) body, at most
+    /// `DEFAULT_MAX_PARTS` of them; see `parse_multipart` for another limit.
+    pub fn multipart(&self) -> Result<Vec<Part>, MultipartError> {
+        let ct = self
+            .headers
+            .get("content-type")
+            .and_then(ContentType::parse)
+            .filter(|ct| ct.media_type == "multipart")
+            .ok_or(MultipartError::NotMultipart)?;
+        let boundary = ct
+            .param("boundary")
+            .ok_or(MultipartError::MissingBoundary)?;
+        parse_multipart(&self.data.body, boundary, DEFAULT_MAX_PARTS)
+    }
+
+    /// Deserializes the body as JSON. The request must declare `application/json` (or
+    /// a `+json` type such as `application/problem+json`).
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, JsonError> {
+        let is_json = self
+            .headers
+            .get("content-type")
+            .and_then(ContentType::parse)
+            .is_some_and(|ct| {
+                ct.media_type == "application"
+                    && (ct.subtype == "json" || ct.subtype.ends_with("+json"))
+            });
+        if !is_json {
+            return Err(JsonError::UnsupportedMediaType);
+        }
+        serde_json::from_slice(&self.data.body).map_err(|e| JsonError::Malformed(e.to_string()))
+    }
+}
+
+/// Why `Request::json` could not produce a value.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    /// The `Content-Type` is missing or not JSON.
+    UnsupportedMediaType,
+    /// The body is not valid JSON for the requested type; holds the parser's message.
+    Malformed(String),
+}
+
+#[cfg(feature = "serde")]
+impl JsonError {
+    /// 415 for a wrong content type, 400 for a body that does not parse.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            Self::Malformed(_) => StatusCode::BadRequest,
+        }
+    }
+}
+
+/// Response payload. `Shared` lets many responses point at the same bytes
+/// (e.g. assets embedded in the binary) without copying them per request.
 #[derive(Debug, Clone)]
+pub enum Body {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::Owned(Vec::new())
+    }
+}
+
+impl Deref for Body {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Body::Owned(bytes) => bytes,
+            Body::Shared(bytes) => bytes,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Owned(bytes)
+    }
+}
+
+impl From<Arc<[u8]>> for Body {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Body::Shared(bytes)
+    }
+}
+
+/// Body produced piece by piece while the connection is writable, for responses whose
+/// total length is unknown when the headers go out.
+pub struct BodyStream {
+    chunks: Box<dyn Iterator<Item = Vec<u8>> + Send>,
+    length_hint: Option<u64>,
+}
+
+impl BodyStream {
+    pub fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        Self {
+            chunks: Box::new(chunks.into_iter()),
+            length_hint: None,
+        }
+    }
+
+    /// Declares the total number of bytes the stream will yield. The response is then
+    /// framed with `Content-Length` instead of chunked encoding, and HEAD can report
+    /// the length without running the stream.
+    pub fn with_length_hint(mut self, length: u64) -> Self {
+        self.length_hint = Some(length);
+        self
+    }
+
+    pub fn length_hint(&self) -> Option<u64> {
+        self.length_hint
+    }
+}
+
+impl Iterator for BodyStream {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.chunks.next()
+    }
+}
+
+impl std::fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("length_hint", &self.length_hint)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Outcome of matching a `Range` header against a body of known length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// First and last byte offsets (inclusive), clamped to the body.
+    Satisfiable { first: u64, last: u64 },
+    /// The range lies outside the body, or several ranges were requested.
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=first-last`, `bytes=first-` or `bytes=-suffix` range against
+/// a body of `len` bytes. Returns `None` for headers that are not byte ranges or are
+/// malformed, which RFC 7233 says to ignore.
+pub fn parse_byte_range(header: &str, len: u64) -> Option<ByteRange> {
+    let (unit, spec) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    if spec.contains(',') {
+        return Some(ByteRange::Unsatisfiable);
+    }
+
+    let (first, last) = spec.trim().split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let digits = |s: &str| {
+        s.parse::<u64>()
+            .ok()
+            .filter(|_| s.bytes().all(|b| b.is_ascii_digit()))
+    };
+    let range = match (first.is_empty(), last.is_empty()) {
+        (true, true) => return None,
+        // `-n` is the final n bytes.
+        (true, false) => {
+            let suffix = digits(last)?;
+            if suffix == 0 || len == 0 {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            (len - suffix.min(len), len - 1)
+        }
+        (false, true) => (digits(first)?, len.saturating_sub(1)),
+        (false, false) => {
+            let (first, last) = (digits(first)?, digits(last)?);
+            if last < first {
+                return None;
+            }
+            (first, last.min(len.saturating_sub(1)))
+        }
+    };
+
+    if range.0 >= len {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Satisfiable {
+        first: range.0,
+        last: range.1,
+    })
+}
+
+/// Body sent straight from an open file once the head is out, with `sendfile` where
+/// the kernel supports it, so large files are never loaded into memory.
+#[derive(Debug)]
+pub struct FileBody {
+    pub(crate) file: File,
+    pub(crate) offset: u64,
+    pub(crate) remaining: u64,
+    /// `sendfile` was refused for this file; read it through `out_buf` instead.
+    pub(crate) buffered: bool,
+}
+
+impl FileBody {
+    pub fn len(&self) -> u64 {
+        self.remaining
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// True when an `Accept-Encoding` value accepts gzip (explicitly or via `*`) with a
+/// non-zero q-value.
+pub fn accepts_gzip(header: &str) -> bool {
+    header.split(',').any(|entry| {
+        let mut params = entry.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        if !["gzip", "x-gzip", "*"]
+            .iter()
+            .any(|c| coding.eq_ignore_ascii_case(c))
+        {
+            return false;
+        }
+        let q = params
+            .filter_map(|p| p.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, v)| v.trim().parse::<f32>().ok());
+        q.is_some_and(|q| q > 0.0)
+    })
+}
+
+/// Which response content types gzip may touch. Patterns are full media types
+/// (`application/json`) or a type with a wildcard subtype (`text/*`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionFilter {
+    /// Compress only the listed types; responses without a content type are skipped.
+    Allow(Vec<String>),
+    /// Compress everything except the listed types.
+    Deny(Vec<String>),
+}
+
+impl Default for CompressionFilter {
+    /// Skips formats that are already compressed.
+    fn default() -> Self {
+        let compressed = [
+            "image/png",
+            "image/jpeg",
+            "image/gif",
+            "image/webp",
+            "image/avif",
+            "video/*",
+            "audio/*",
+            "font/woff",
+            "font/woff2",
+            "application/zip",
+            "application/gzip",
+            "application/x-gzip",
+            "application/x-bzip2",
+            "application/x-7z-compressed",
+            "application/x-rar-compressed",
+        ];
+        CompressionFilter::Deny(compressed.iter().map(|t| t.to_string()).collect())
+    }
+}
+
+impl CompressionFilter {
+    pub fn allows(&self, content_type: Option<&ContentType>) -> bool {
+        let matches = |patterns: &[String]| {
+            content_type.is_some_and(|ct| {
+                patterns
+                    .iter()
+                    .any(|pattern| match pattern.split_once('/') {
+                        Some((media, "*")) => media.eq_ignore_ascii_case(&ct.media_type),
+                        _ => pattern.eq_ignore_ascii_case(&ct.essence()),
+                    })
+            })
+        };
+        match self {
+            CompressionFilter::Allow(patterns) => matches(patterns),
+            CompressionFilter::Deny(patterns) => !matches(patterns),
+        }
+    }
+}
+
+/// Largest chunk `to_bytes_chunked` emits.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Zero-size chunk with an empty trailer section, ending a chunked body.
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// Appends `data` to `out` as one chunk frame: hex size, CRLF, data, CRLF.
+pub fn encode_chunk(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Line terminator that textual response bodies can be normalized to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// How a streamed body is delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFraming {
+    /// `Transfer-Encoding: chunked`, ended by a zero-size chunk.
+    Chunked,
+    /// Raw bytes ended by closing the connection (HTTP/1.0 peers).
+    Close,
+    /// Raw bytes announced by `Content-Length`; the value is how many are still owed.
+    Length(u64),
+}
+
+#[derive(Debug)]
 pub struct Response {
     pub version: String,
     pub status: StatusCode,
     pub headers: HeaderMap,
-    pub body: Vec<u8>,
+    pub body: Body,
+    /// Set for responses built with `Response::streaming`; takes the place of `body`.
+    pub stream: Option<BodyStream>,
+    /// Set for responses built with `Response::raw`; sent verbatim instead of being
+    /// serialized from the other fields.
+    pub raw: Option<Vec<u8>>,
+    /// Set for responses built with `Response::file`; takes the place of `body`.
+    pub file: Option<FileBody>,
 }
 
 impl Response {
+    /// Response whose body comes from `stream`. Streams with a length hint are sent
+    /// with `Content-Length`; otherwise HTTP/1.1 clients get chunked framing and
+    /// HTTP/1.0 clients get the raw bytes and a connection close at the end.
+    pub fn streaming(
+        version: &str,
+        status: StatusCode,
+        content_type: &str,
+        stream: BodyStream,
+    ) -> Self {
+        let mut headers = HeaderMap::default();
+        headers.insert("Content-Type", content_type);
+
+        Self {
+            version: version.to_string(),
+            status,
+            headers,
+            body: Body::default(),
+            stream: Some(stream),
+            raw: None,
+            file: None,
+        }
+    }
+
+    /// Response whose body is the whole of `file`, sent with `Content-Length` and
+    /// without copying the file into memory.
+    pub fn file(
+        version: &str,
+        status: StatusCode,
+        content_type: &str,
+        file: File,
+    ) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        let mut headers = HeaderMap::default();
+        headers.insert("Content-Type", content_type);
+        headers.insert("Content-Length", &len.to_string());
+
+        Ok(Self {
+            version: version.to_string(),
+            status,
+            headers,
+            body: Body::default(),
+            stream: None,
+            raw: None,
+            file: Some(FileBody {
+                file,
+                offset: 0,
+                remaining: len,
+                buffered: false,
+            }),
+        })
+    }
+
+    /// Fully formed response bytes (status line, headers and body) that are sent as-is,
+    /// e.g. a replayed capture or bytes relayed from an upstream server. The caller is
+    /// responsible for their framing; the connection is closed once they are sent.
+    pub fn raw(bytes: Vec<u8>) -> Self {
+        Self {
+            version: "HTTP/1.1".to_string(),
+            status: StatusCode::Ok,
+            headers: HeaderMap::default(),
+            body: Body::default(),
+            stream: None,
+            raw: Some(bytes),
+            file: None,
+        }
+    }
+
+    /// Detaches the streamed body, if any, and rewrites the framing headers to match
+    /// the way it will be sent. Call before `to_bytes`, which then yields the head only.
+    /// 1xx, 204 and 304 responses never have a body, so their stream is discarded and
+    /// no chunked framing is announced.
+    pub fn take_stream(&mut self) -> Option<(BodyStream, StreamFraming)> {
+        let stream = self.stream.take()?;
+        if !self.status.allows_body() {
+            self.headers.remove("transfer-encoding");
+            return None;
+        }
+        self.headers.remove("content-length");
+
+        if let Some(length) = stream.length_hint() {
+            self.headers.remove("transfer-encoding");
+            self.headers.insert("Content-Length", &length.to_string());
+            return Some((stream, StreamFraming::Length(length)));
+        }
+        if self.version == "HTTP/1.0" {
+            self.headers.remove("transfer-encoding");
+            self.headers.insert("Connection", "close");
+            return Some((stream, StreamFraming::Close));
+        }
+        self.headers.insert("Transfer-Encoding", "chunked");
+        Some((stream, StreamFraming::Chunked))
+    }
+
+    /// Rewrites line endings in a textual in-memory body and updates `Content-Length`.
+    /// Binary, streamed and raw responses are left alone.
+    pub fn normalize_newlines(&mut self, to: LineEnding) {
+        let textual = self
+            .headers
+            .get("content-type")
+            .and_then(ContentType::parse)
+            .is_some_and(|ct| ct.is_textual());
+        if !textual || self.body.is_empty() || self.stream.is_some() || self.raw.is_some() {
+            return;
+        }
+
+        let mut out = Vec::with_capacity(self.body.len());
+        let mut prev = 0u8;
+        for &byte in self.body.iter() {
+            match (to, byte) {
+                (LineEnding::Crlf, b'\n') if prev != b'\r' => out.extend_from_slice(b"\r\n"),
+                (LineEnding::Lf, b'\n') if prev == b'\r' => {
+                    out.pop();
+                    out.push(b'\n');
+                }
+                _ => out.push(byte),
+            }
+            prev = byte;
+        }
+
+        if self.headers.get("content-length").is_some() {
+            self.headers
+                .insert("Content-Length", &out.len().to_string());
+        }
+        self.body = Body::Owned(out);
+    }
+
+    /// Marks the response as a download saved as `filename`. Non-ASCII names get an
+    /// RFC 5987 `filename*` parameter next to an ASCII fallback for older clients.
+    pub fn attachment(&mut self, filename: &str) -> &mut Self {
+        let fallback: String = filename
+            .chars()
+            .map(|c| match c {
+                '"' | '\\' => format!("\\{c}"),
+                c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+                _ => "_".to_string(),
+            })
+            .collect();
+        let mut value = format!("attachment; filename=\"{fallback}\"");
+        if !filename.is_ascii() {
+            value.push_str("; filename*=UTF-8''");
+            for byte in filename.bytes() {
+                if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                    value.push(char::from(byte));
+                } else {
+                    value.push_str(&format!("%{byte:02X}"));
+                }
+            }
+        }
+        self.headers.insert("Content-Disposition", &value);
+        self
+    }
+
+    /// Narrows a `200 OK` in-memory or file body to the bytes `range_header` asks for,
+    /// answering 206 with `Content-Range`, or 416 when the range cannot be served.
+    /// Returns whether the response changed; streamed and raw responses never do.
+    pub fn apply_range(&mut self, range_header: &str) -> bool {
+        if self.status.code() != 200 || self.stream.is_some() || self.raw.is_some() {
+            return false;
+        }
+        let len = self
+            .file
+            .as_ref()
+            .map_or(self.body.len() as u64, FileBody::len);
+        let Some(range) = parse_byte_range(range_header, len) else {
+            return false;
+        };
+
+        match range {
+            ByteRange::Satisfiable { first, last } => {
+                let count = last - first + 1;
+                match self.file.as_mut() {
+                    Some(file) => {
+                        file.offset += first;
+                        file.remaining = count;
+                    }
+                    None => {
+                        self.body = Body::Owned(self.body[first as usize..=last as usize].to_vec());
+                    }
+                }
+                self.status = StatusCode::PartialContent;
+                self.headers
+                    .insert("Content-Range", &format!("bytes {first}-{last}/{len}"));
+                self.headers.insert("Content-Length", &count.to_string());
+            }
+            ByteRange::Unsatisfiable => {
+                self.file = None;
+                self.body = Body::default();
+                self.status = StatusCode::RangeNotSatisfiable;
+                self.headers
+                    .insert("Content-Range", &format!("bytes */{len}"));
+                self.headers.insert("Content-Length", "0");
+            }
+        }
+        self.headers.insert("Accept-Ranges", "bytes");
+        true
+    }
+
+    /// Gzips the in-memory body and sets `Content-Encoding`, keeping whatever framing
+    /// the response already uses. Responses that are already encoded, empty, streamed
+    /// or raw are left alone; returns whether the body was compressed.
+    pub fn compress_gzip(&mut self) -> bool {
+        if self.headers.get("content-encoding").is_some()
+            || self.body.is_empty()
+            || !self.status.allows_body()
+            || self.stream.is_some()
+            || self.raw.is_some()
+        {
+            return false;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let compressed = match encoder.write_all(&self.body).and_then(|_| encoder.finish()) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                crate::warn!("gzip compression failed", "error" => err);
+                return false;
+            }
+        };
+
+        if self.headers.get("content-length").is_some() {
+            self.headers
+                .insert("Content-Length", &compressed.len().to_string());
+        }
+        self.headers.insert("Content-Encoding", "gzip");
+        self.headers.insert("Vary", "Accept-Encoding");
+        self.body = Body::Owned(compressed);
+        true
+    }
+
+    /// Switches the in-memory body to `Transfer-Encoding: chunked` framing. HTTP/1.0
+    /// peers cannot decode chunks, so their responses keep `Content-Length`.
+    pub fn use_chunked(&mut self) -> &mut Self {
+        if self.version != "HTTP/1.0" {
+            self.headers.remove("content-length");
+            self.headers.insert("Transfer-Encoding", "chunked");
+        }
+        self
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.headers
+            .get("transfer-encoding")
+            .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Serializes the response with `body` split into chunk frames and a terminating
+    /// zero-size chunk.
+    pub fn to_bytes_chunked(&self) -> Vec<u8> {
+        let mut out = self.serialize_head(false);
+        for piece in self.body.chunks(CHUNK_SIZE) {
+            encode_chunk(&mut out, piece);
+        }
+        out.extend_from_slice(LAST_CHUNK);
+        out
+    }
+
+    /// Serializes the response; raw responses are returned unchanged. Framing mistakes
+    /// made by a handler (a `Content-Length` that disagrees with the body, or a body on
+    /// 1xx/204/304) panic in debug builds; release builds log a warning and send
+    /// corrected framing instead. 1xx/204/304 responses are sent without
+    /// `Content-Length` or `Transfer-Encoding` either way.
     pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(raw) = &self.raw {
+            return raw.clone();
+        }
+        if self.is_chunked() && self.status.allows_body() {
+            return self.to_bytes_chunked();
+        }
+        let violation = self.framing_violation();
+        if let Some(problem) = &violation {
+            if cfg!(debug_assertions) {
+                panic!("invalid response framing: {problem}");
+            }
+            crate::warn!("repairing response framing", "problem" => problem);
+        }
+        self.serialize(violation.is_some())
+    }
+
+    fn framing_violation(&self) -> Option<String> {
+        if !self.status.allows_body() {
+            if !self.body.is_empty() {
+                return Some(format!(
+                    "{} response carries a {} byte body",
+                    self.status.code(),
+                    self.body.len()
+                ));
+            }
+            return None;
+        }
+
+        let declared = self.headers.get("content-length")?;
+        if declared.parse::<usize>().ok() == Some(self.body.len()) {
+            return None;
+        }
+        Some(format!(
+            "Content-Length is {declared} but the body has {} bytes",
+            self.body.len()
+        ))
+    }
+
+    /// Status line and headers only, for a response whose body was detached with
+    /// `take_stream` and is sent separately.
+    pub fn head_bytes(&self) -> Vec<u8> {
+        self.serialize_head(false)
+    }
+
+    fn serialize(&self, repair: bool) -> Vec<u8> {
+        let mut out = self.serialize_head(repair);
+        if self.status.allows_body() || !repair {
+            out.extend_from_slice(&self.body);
+        }
+        out
+    }
+
+    fn serialize_head(&self, repair: bool) -> Vec<u8> {
+        let allows_body = self.status.allows_body();
         let mut out = Vec::new();
         let status_line = format!(
             "{} {} {}\r\n",
@@ -120,12 +1091,19 @@ impl Response {
         out.extend_from_slice(status_line.as_bytes());
 
         for (k, v) in self.headers.iter() {
-            let line = format!("{k}: {v}\r\n");
+            // 1xx, 204 and 304 responses never have a body, so they carry no framing.
+            if !allows_body && (k == "content-length" || k == "transfer-encoding") {
+                continue;
+            }
+            let line = if repair && k == "content-length" {
+                format!("{k}: {}\r\n", self.body.len())
+            } else {
+                format!("{k}: {v}\r\n")
+            };
             out.extend_from_slice(line.as_bytes());
         }
 
         out.extend_from_slice(b"\r\n");
-        out.extend_from_slice(&self.body);
         out
     }
 }
@@ -134,17 +1112,630 @@ pub fn response_with_body(
     version: &str,
     status: StatusCode,
     content_type: &str,
-    body: Vec<u8>,
+    body: impl Into<Body>,
 ) -> Response {
+    let body = body.into();
     let mut headers = HeaderMap::default();
     headers.insert("Content-Type", content_type);
     headers.insert("Content-Length", &body.len().to_string());
-    headers.insert("Connection", "close");
 
     Response {
         version: version.to_string(),
         status,
         headers,
         body,
+        stream: None,
+        raw: None,
+        file: None,
+    }
+}
+
+/// `text` as `text/plain; charset=utf-8`.
+pub fn text_response(version: &str, status: StatusCode, text: &str) -> Response {
+    response_with_body(
+        version,
+        status,
+        "text/plain; charset=utf-8",
+        text.as_bytes().to_vec(),
+    )
+}
+
+/// `html` as `text/html; charset=utf-8`.
+pub fn html_response(version: &str, status: StatusCode, html: &str) -> Response {
+    response_with_body(
+        version,
+        status,
+        "text/html; charset=utf-8",
+        html.as_bytes().to_vec(),
+    )
+}
+
+/// Already-serialized `json` as `application/json`.
+pub fn json_str_response(version: &str, status: StatusCode, json: &str) -> Response {
+    response_with_body(
+        version,
+        status,
+        "application/json",
+        json.as_bytes().to_vec(),
+    )
+}
+
+/// `value` serialized as `application/json`, or an empty `500` when it cannot be;
+/// see `ResponseBuilder::json`.
+#[cfg(feature = "serde")]
+pub fn json_response<T: serde::Serialize + ?Sized>(
+    version: &str,
+    status: StatusCode,
+    value: &T,
+) -> Response {
+    Response::builder()
+        .status(status)
+        .json(value)
+        .build(version)
+}
+
+/// Empty-bodied 3xx pointing the client at `location`.
+pub fn redirect(version: &str, status: StatusCode, location: &str) -> Response {
+    let mut headers = HeaderMap::default();
+    headers.insert("Location", location);
+    headers.insert("Content-Length", "0");
+
+    Response {
+        version: version.to_string(),
+        status,
+        headers,
+        body: Default::default(),
+        stream: None,
+        raw: None,
+        file: None,
+    }
+}
+
+/// Chainable construction of a `Response`, from `Response::builder`. `build` fills in
+/// `Content-Length`, and a `Content-Type` matching how the body was set unless one was
+/// given with `header`.
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Body,
+    default_content_type: Option<&'static str>,
+    /// A `json` value failed to serialize, so `build` answers `500` whatever was set
+    /// after it.
+    json_failed: bool,
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self {
+            status: StatusCode::Ok,
+            headers: HeaderMap::default(),
+            body: Body::default(),
+            default_content_type: None,
+            json_failed: false,
+        }
+    }
+}
+
+impl Response {
+    /// A `200` with no headers and an empty body, to be filled in.
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::default()
+    }
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Adds a header line; calling it again with the same name adds another value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sends `body`, as `application/octet-stream` unless told otherwise.
+    pub fn body(self, body: impl Into<Body>) -> Self {
+        self.body_as(body.into(), "application/octet-stream")
+    }
+
+    /// Sends `text` as `text/plain; charset=utf-8` unless told otherwise.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.body_as(text.into().into_bytes().into(), "text/plain; charset=utf-8")
+    }
+
+    /// Sends `value` serialized as `application/json`. A value that cannot be
+    /// serialized (e.g. a map with non-string keys) is logged and turns the response
+    /// into an empty `500`, even if the status or body is set again later.
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::Serialize + ?Sized>(self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(bytes) => self.body_as(bytes.into(), "application/json"),
+            Err(e) => {
+                eprintln!("could not serialize JSON response: {e}");
+                Self {
+                    json_failed: true,
+                    ..self
+                }
+            }
+        }
+    }
+
+    fn body_as(mut self, body: Body, content_type: &'static str) -> Self {
+        self.body = body;
+        self.default_content_type = Some(content_type);
+        self
+    }
+
+    /// The response for a request of HTTP `version`. Statuses that never carry a
+    /// body (1xx, 204, 304) are built without one.
+    pub fn build(mut self, version: &str) -> Response {
+        if self.json_failed {
+            self.status = StatusCode::InternalServerError;
+            self.body = Body::default();
+            self.default_content_type = None;
+        }
+        let mut headers = self.headers;
+        let body = if self.status.allows_body() {
+            if headers.get("content-type").is_none()
+                && let Some(content_type) = self.default_content_type
+            {
+                headers.insert("Content-Type", content_type);
+            }
+            headers.insert("Content-Length", &self.body.len().to_string());
+            self.body
+        } else {
+            headers.remove("content-length");
+            Body::default()
+        };
+
+        Response {
+            version: version.to_string(),
+            status: self.status,
+            headers,
+            body,
+            stream: None,
+            raw: None,
+            file: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BodyStream, ByteRange, CHUNK_SIZE, ContentType, HeaderMap, LineEnding, Response,
+        StatusCode, accepts_gzip, html_response, json_str_response, parse_accept, parse_byte_range,
+        redirect, response_with_body, text_response,
+    };
+
+    #[test]
+    fn text_html_and_json_helpers_label_their_bodies() {
+        let text = text_response("HTTP/1.1", StatusCode::NotFound, "missing");
+        assert_eq!(text.status.code(), 404);
+        assert_eq!(
+            text.headers.get("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(text.headers.get("content-length"), Some("7"));
+        assert_eq!(&text.body[..], b"missing");
+
+        let html = html_response("HTTP/1.1", StatusCode::Ok, "<p>hi</p>");
+        assert_eq!(
+            html.headers.get("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let json = json_str_response("HTTP/1.1", StatusCode::Ok, r#"{"a":1}"#);
+        assert_eq!(json.headers.get("content-type"), Some("application/json"));
+        assert_eq!(&json.body[..], br#"{"a":1}"#);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = super::json_response("HTTP/1.1", StatusCode::Created, &[1, 2]);
+            assert_eq!(json.status.code(), 201);
+            assert_eq!(json.headers.get("content-type"), Some("application/json"));
+            assert_eq!(&json.body[..], b"[1,2]");
+        }
+    }
+
+    #[test]
+    fn builder_sets_framing_and_default_content_types() {
+        let resp = Response::builder()
+            .status(StatusCode::Created)
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .text("made")
+            .build("HTTP/1.1");
+        assert_eq!(resp.status.code(), 201);
+        assert_eq!(
+            resp.headers.get("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(resp.headers.get("content-length"), Some("4"));
+        assert_eq!(resp.headers.get_all("set-cookie").count(), 2);
+        assert_eq!(&resp.body[..], b"made");
+
+        let resp = Response::builder()
+            .header("Content-Type", "image/png")
+            .body(vec![1, 2, 3])
+            .build("HTTP/1.1");
+        assert_eq!(resp.headers.get("content-type"), Some("image/png"));
+        assert_eq!(resp.headers.get("content-length"), Some("3"));
+
+        let resp = Response::builder().build("HTTP/1.0");
+        assert_eq!(resp.status.code(), 200);
+        assert_eq!(resp.headers.get("content-type"), None);
+        assert_eq!(resp.headers.get("content-length"), Some("0"));
+
+        let resp = Response::builder()
+            .status(StatusCode::NoContent)
+            .text("dropped")
+            .build("HTTP/1.1");
+        assert!(resp.body.is_empty());
+        assert_eq!(resp.headers.get("content-length"), None);
+        assert!(resp.to_bytes().ends_with(b"\r\n\r\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn builder_serializes_json_or_fails_with_500() {
+        use std::collections::HashMap;
+
+        let resp = Response::builder()
+            .json(&serde_json::json!({"ok": true}))
+            .build("HTTP/1.1");
+        assert_eq!(resp.headers.get("content-type"), Some("application/json"));
+        assert_eq!(&resp.body[..], br#"{"ok":true}"#);
+
+        let unserializable: HashMap<(u8, u8), u8> = HashMap::from([((1, 2), 3)]);
+        let resp = Response::builder().json(&unserializable).build("HTTP/1.1");
+        assert_eq!(resp.status.code(), 500);
+        assert!(resp.body.is_empty());
+        assert_eq!(resp.headers.get("content-type"), None);
+
+        let resp = Response::builder()
+            .json(&unserializable)
+            .status(StatusCode::Created)
+            .text("fallback")
+            .build("HTTP/1.1");
+        assert_eq!(resp.status.code(), 500);
+        assert!(resp.body.is_empty());
+        assert_eq!(resp.headers.get("content-length"), Some("0"));
+    }
+
+    #[test]
+    fn parse_accept_orders_by_q_value() {
+        let ranges = parse_accept("text/html;q=0.9, application/json");
+        assert_eq!(
+            ranges,
+            vec![
+                ("application/json".to_string(), 1.0),
+                ("text/html".to_string(), 0.9),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_accept_prefers_specific_ranges_on_equal_q() {
+        let ranges = parse_accept("*/*, text/*, text/plain;q=0.5, text/html");
+        let types: Vec<&str> = ranges.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(types, vec!["text/html", "text/*", "*/*", "text/plain"]);
+    }
+
+    #[test]
+    fn parse_accept_drops_unacceptable_and_malformed_entries() {
+        let ranges = parse_accept("image/png;q=0, garbage, Text/HTML;Q=abc");
+        assert_eq!(ranges, vec![("text/html".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn header_map_keeps_repeated_values_in_order() {
+        let mut headers = HeaderMap::default();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(
+            headers.get_all("set-cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(headers.iter().count(), 2);
+
+        headers.insert("Set-Cookie", "c=3");
+        assert_eq!(headers.get_all("set-cookie").collect::<Vec<_>>(), ["c=3"]);
+        assert_eq!(headers.remove("set-cookie").as_deref(), Some("c=3"));
+        assert_eq!(headers.get_all("set-cookie").count(), 0);
+    }
+
+    #[test]
+    fn to_bytes_writes_one_line_per_header_value() {
+        let mut resp = response_with_body("HTTP/1.1", StatusCode::Ok, "text/plain", b"ok".to_vec());
+        resp.headers.append("Set-Cookie", "a=1");
+        resp.headers.append("Set-Cookie", "b=2");
+        let text = String::from_utf8(resp.to_bytes()).unwrap();
+        let cookies: Vec<&str> = text
+            .lines()
+            .filter(|line| line.starts_with("set-cookie:"))
+            .collect();
+        assert_eq!(cookies, ["set-cookie: a=1", "set-cookie: b=2"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Content-Length is 999 but the body has 5 bytes")]
+    fn to_bytes_panics_on_wrong_content_length_in_debug() {
+        let mut resp =
+            response_with_body("HTTP/1.1", StatusCode::Ok, "text/plain", b"hello".to_vec());
+        resp.headers.insert("Content-Length", "999");
+        let _ = resp.to_bytes();
+    }
+
+    #[test]
+    fn serialize_repairs_content_length_and_bodyless_statuses() {
+        let mut resp =
+            response_with_body("HTTP/1.1", StatusCode::Ok, "text/plain", b"hello".to_vec());
+        resp.headers.insert("Content-Length", "999");
+        let text = String::from_utf8(resp.serialize(true)).expect("ascii response");
+        assert!(text.contains("content-length: 5\r\n"));
+        assert!(text.ends_with("\r\n\r\nhello"));
+
+        let no_content = response_with_body(
+            "HTTP/1.1",
+            StatusCode::NoContent,
+            "text/plain",
+            b"x".to_vec(),
+        );
+        assert!(no_content.framing_violation().is_some());
+        assert!(no_content.serialize(true).ends_with(b"\r\n\r\n"));
+
+        for status in [StatusCode::NoContent, StatusCode::NotModified] {
+            let mut resp = response_with_body("HTTP/1.1", status, "text/plain", Vec::new());
+            resp.use_chunked();
+            let text = String::from_utf8(resp.to_bytes()).expect("ascii response");
+            assert!(!text.contains("transfer-encoding"), "{text}");
+            assert!(!text.contains("content-length"), "{text}");
+            assert!(text.ends_with("\r\n\r\n"));
+
+            let resp = response_with_body("HTTP/1.1", status, "text/plain", Vec::new());
+            let text = String::from_utf8(resp.to_bytes()).expect("ascii response");
+            assert!(!text.contains("content-length"), "{text}");
+        }
+    }
+
+    #[test]
+    fn content_type_parses_charset_parameter() {
+        let ct = ContentType::parse("Application/JSON; charset=utf-8").expect("valid type");
+        assert_eq!(ct.media_type, "application");
+        assert_eq!(ct.subtype, "json");
+        assert_eq!(ct.essence(), "application/json");
+        assert_eq!(ct.param("Charset"), Some("utf-8"));
+    }
+
+    #[test]
+    fn content_type_unquotes_boundary_parameter() {
+        let ct = ContentType::parse("multipart/form-data; boundary=\"x\"").expect("valid type");
+        assert_eq!(ct.essence(), "multipart/form-data");
+        assert_eq!(ct.param("boundary"), Some("x"));
+
+        let ct = ContentType::parse(r#"multipart/mixed; boundary="a;\"b"; charset=us-ascii"#)
+            .expect("valid type");
+        assert_eq!(ct.param("boundary"), Some("a;\"b"));
+        assert_eq!(ct.param("charset"), Some("us-ascii"));
+    }
+
+    #[test]
+    fn content_type_rejects_values_without_subtype() {
+        assert!(ContentType::parse("text").is_none());
+        assert!(ContentType::parse("/plain").is_none());
+    }
+
+    #[test]
+    fn take_stream_discards_body_for_not_modified() {
+        let stream = BodyStream::new(vec![b"stale".to_vec()]);
+        let mut resp =
+            Response::streaming("HTTP/1.1", StatusCode::NotModified, "text/plain", stream);
+
+        assert!(resp.take_stream().is_none());
+        let bytes = String::from_utf8(resp.to_bytes()).expect("head is UTF-8");
+        assert!(!bytes.contains("transfer-encoding"));
+        assert!(!bytes.contains("content-length"));
+        assert!(bytes.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn normalize_newlines_converts_text_in_both_directions() {
+        let mut resp = response_with_body(
+            "HTTP/1.1",
+            StatusCode::Ok,
+            "text/plain; charset=utf-8",
+            b"a\nb\r\nc\n".to_vec(),
+        );
+        resp.normalize_newlines(LineEnding::Crlf);
+        assert_eq!(&resp.body[..], b"a\r\nb\r\nc\r\n");
+        assert_eq!(resp.headers.get("content-length"), Some("9"));
+
+        resp.normalize_newlines(LineEnding::Lf);
+        assert_eq!(&resp.body[..], b"a\nb\nc\n");
+        assert_eq!(resp.headers.get("content-length"), Some("6"));
+    }
+
+    #[test]
+    fn normalize_newlines_leaves_binary_bodies_alone() {
+        let bytes = vec![0x89, b'P', b'N', b'G', b'\n', 0x1a, b'\n'];
+        let mut resp = response_with_body("HTTP/1.1", StatusCode::Ok, "image/png", bytes.clone());
+        resp.normalize_newlines(LineEnding::Crlf);
+        assert_eq!(&resp.body[..], &bytes[..]);
+        assert_eq!(resp.headers.get("content-length"), Some("7"));
+    }
+
+    #[test]
+    fn chunked_response_encodes_body_in_frames() {
+        let mut body = vec![b'a'; CHUNK_SIZE];
+        body.extend_from_slice(b"tail");
+        let mut resp = response_with_body("HTTP/1.1", StatusCode::Ok, "text/plain", body);
+        resp.use_chunked();
+
+        let bytes = resp.to_bytes();
+        let text = String::from_utf8_lossy(&bytes);
+        let (head, payload) = text.split_once("\r\n\r\n").expect("response head");
+        assert!(head.contains("transfer-encoding: chunked"));
+        assert!(!head.contains("content-length"));
+        let expected = format!(
+            "4000\r\n{}\r\n4\r\ntail\r\n0\r\n\r\n",
+            "a".repeat(CHUNK_SIZE)
+        );
+        assert_eq!(payload, expected);
+
+        let mut old = response_with_body("HTTP/1.0", StatusCode::Ok, "text/plain", b"x".to_vec());
+        old.use_chunked();
+        assert!(!old.is_chunked());
+        let bytes = old.to_bytes();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("content-length: 1\r\n"));
+        assert!(text.ends_with("\r\n\r\nx"));
+    }
+
+    #[test]
+    fn accepts_gzip_honours_q_values_and_wildcards() {
+        assert!(accepts_gzip("deflate, gzip;q=0.5"));
+        assert!(accepts_gzip("br, *"));
+        assert!(!accepts_gzip("gzip;q=0, deflate"));
+        assert!(!accepts_gzip("identity"));
+    }
+
+    #[test]
+    fn redirect_sets_location_and_empty_body() {
+        let resp = redirect("HTTP/1.1", StatusCode::SeeOther, "/login");
+        let text = String::from_utf8(resp.to_bytes()).expect("utf-8");
+        assert!(text.starts_with("HTTP/1.1 303 See Other\r\n"));
+        assert!(text.contains("location: /login\r\n"));
+        assert!(text.contains("content-length: 0\r\n"));
+        assert!(text.ends_with("\r\n\r\n"));
+        assert_eq!(StatusCode::MovedPermanently.code(), 301);
+        assert_eq!(StatusCode::Found.reason(), "Found");
+    }
+
+    #[test]
+    fn byte_ranges_are_parsed_against_the_body_length() {
+        let sat = |first, last| Some(ByteRange::Satisfiable { first, last });
+        assert_eq!(parse_byte_range("bytes=0-4", 10), sat(0, 4));
+        assert_eq!(parse_byte_range("bytes=5-", 10), sat(5, 9));
+        assert_eq!(parse_byte_range("bytes=-3", 10), sat(7, 9));
+        assert_eq!(parse_byte_range("bytes=-30", 10), sat(0, 9));
+        assert_eq!(parse_byte_range("bytes=8-100", 10), sat(8, 9));
+        assert_eq!(
+            parse_byte_range("bytes=10-", 10),
+            Some(ByteRange::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_byte_range("bytes=0-1,4-5", 10),
+            Some(ByteRange::Unsatisfiable)
+        );
+        assert_eq!(parse_byte_range("bytes=5-2", 10), None);
+        assert_eq!(parse_byte_range("items=0-1", 10), None);
+        assert_eq!(parse_byte_range("bytes=x-1", 10), None);
+    }
+
+    #[test]
+    fn apply_range_slices_the_body() {
+        let mut resp = response_with_body(
+            "HTTP/1.1",
+            StatusCode::Ok,
+            "text/plain",
+            b"0123456789".to_vec(),
+        );
+        assert!(resp.apply_range("bytes=2-5"));
+        assert_eq!(resp.status.code(), 206);
+        assert_eq!(&resp.body[..], b"2345");
+        assert_eq!(resp.headers.get("content-range"), Some("bytes 2-5/10"));
+        assert_eq!(resp.headers.get("content-length"), Some("4"));
+
+        let mut resp = response_with_body(
+            "HTTP/1.1",
+            StatusCode::Ok,
+            "text/plain",
+            b"0123456789".to_vec(),
+        );
+        assert!(resp.apply_range("bytes=20-"));
+        assert_eq!(resp.status.code(), 416);
+        assert!(resp.body.is_empty());
+        assert_eq!(resp.headers.get("content-range"), Some("bytes */10"));
+
+        let mut resp = response_with_body(
+            "HTTP/1.1",
+            StatusCode::Ok,
+            "text/plain",
+            b"0123456789".to_vec(),
+        );
+        assert!(!resp.apply_range("lines=1-2"));
+        assert_eq!(&resp.body[..], b"0123456789");
+    }
+
+    #[test]
+    fn attachment_quotes_ascii_and_encodes_unicode_names() {
+        let mut resp = response_with_body("HTTP/1.1", StatusCode::Ok, "text/plain", Vec::new());
+        resp.attachment("report \"final\".txt");
+        assert_eq!(
+            resp.headers.get("content-disposition"),
+            Some("attachment; filename=\"report \\\"final\\\".txt\"")
+        );
+
+        resp.attachment("naïve €.pdf");
+        assert_eq!(
+            resp.headers.get("content-disposition"),
+            Some(
+                "attachment; filename=\"na_ve _.pdf\"; filename*=UTF-8''na%C3%AFve%20%E2%82%AC.pdf"
+            )
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_checks_content_type_then_parses() {
+        use std::collections::HashMap;
+
+        use super::{HeaderMap, HttpMethod, JsonError, Request};
+        use crate::router::Data;
+
+        let post = |content_type: Option<&str>, body: &str| {
+            let mut headers = HeaderMap::default();
+            if let Some(value) = content_type {
+                headers.insert("Content-Type", value);
+            }
+            let mut data = Data::default();
+            data.body = body.as_bytes().to_vec();
+            Request {
+                method: HttpMethod::Post,
+                path: "/".to_string(),
+                query: String::new(),
+                version: "HTTP/1.1".to_string(),
+                headers,
+                trailers: HeaderMap::default(),
+                data,
+            }
+        };
+
+        let parsed: HashMap<String, u32> =
+            post(Some("application/json; charset=utf-8"), r#"{"a":1}"#)
+                .json()
+                .unwrap();
+        assert_eq!(parsed["a"], 1);
+        assert!(
+            post(Some("application/merge-patch+json"), "{}")
+                .json::<HashMap<String, u32>>()
+                .is_ok()
+        );
+
+        let wrong_type = post(Some("text/plain"), "{}").json::<HashMap<String, u32>>();
+        assert_eq!(wrong_type, Err(JsonError::UnsupportedMediaType));
+        assert_eq!(wrong_type.unwrap_err().status().code(), 415);
+        assert!(post(None, "{}").json::<HashMap<String, u32>>().is_err());
+
+        let malformed = post(Some("application/json"), r#"{"a":"x"}"#)
+            .json::<HashMap<String, u32>>()
+            .unwrap_err();
+        assert!(matches!(malformed, JsonError::Malformed(_)));
+        assert_eq!(malformed.status().code(), 400);
     }
 }
@@ -0,0 +1,6 @@
+pub mod conn;
+pub mod handlers;
+pub mod https;
+pub mod multipart;
+pub mod router;
+pub mod utils;
@@ -1,17 +1,12 @@
-mod conn;
-mod handlers;
-mod https;
-mod router;
-mod utils;
-
-use crate::handlers::register_routes;
-use crate::router::Router;
+use http_rust::handlers::register_routes;
+use http_rust::info;
+use http_rust::router::Router;
 
 fn main() {
     // TODO: read config with default conf location or with -f flag
     // TODO: parse it with serde
     // Add validation with clear startup errors (invalid syntax, invalid route options, duplicate/conflicting listen declarations).
-    
+
     // TODO: loop over over the ports put them in an array
     let mut router = Router::new_on_ports(&[8080, 9090]);
     // TODO: loop over the config and deal with the routes
@@ -23,6 +18,7 @@ fn main() {
 
     info!("Starting server...");
     info!("Server started on ports 8080 and 9090");
-    router.listen_and_serve()
+    if let Err(err) = router.run() {
+        eprintln!("server stopped: {err}");
+    }
 }
-
@@ -0,0 +1,180 @@
+use crate::https::{HeaderMap, parse_parameters};
+
+/// Parts accepted by `Request::multipart` before it gives up. The body as a whole is
+/// already bounded by `ParseOptions::max_body_bytes`, which bounds every part's size.
+pub const DEFAULT_MAX_PARTS: usize = 256;
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub headers: HeaderMap,
+    /// `name` from the part's `Content-Disposition`: the form field it belongs to.
+    pub name: Option<String>,
+    /// `filename` from the part's `Content-Disposition`, set for file inputs.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Why a body could not be split into parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultipartError {
+    /// The request's `Content-Type` is not `multipart/*`.
+    NotMultipart,
+    /// The `Content-Type` has no usable `boundary` parameter.
+    MissingBoundary,
+    /// The body breaks the multipart framing; holds what was wrong.
+    Malformed(&'static str),
+    /// The body has more parts than allowed.
+    TooManyParts,
+}
+
+/// Splits `body` into the parts delimited by `boundary` (RFC 2046 5.1.1). The preamble
+/// before the first delimiter and the epilogue after the closing `--boundary--` are
+/// ignored; a body without the closing delimiter is malformed.
+pub fn parse_multipart(
+    body: &[u8],
+    boundary: &str,
+    max_parts: usize,
+) -> Result<Vec<Part>, MultipartError> {
+    // RFC 2046 caps boundaries at 70 characters.
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(MultipartError::MissingBoundary);
+    }
+    let delimiter = format!("--{boundary}").into_bytes();
+    let next_delimiter = [b"\r\n".as_slice(), &delimiter].concat();
+
+    let mut pos = if body.starts_with(&delimiter) {
+        delimiter.len()
+    } else {
+        find(body, &next_delimiter).ok_or(MultipartError::Malformed("no opening boundary"))?
+            + next_delimiter.len()
+    };
+
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        // Transport padding may follow a delimiter before its line break.
+        let padding = rest
+            .iter()
+            .take_while(|b| matches!(b, b' ' | b'\t'))
+            .count();
+        if !rest[padding..].starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed("boundary not followed by CRLF"));
+        }
+        pos += padding + 2;
+
+        if parts.len() == max_parts {
+            return Err(MultipartError::TooManyParts);
+        }
+        let content_len = find(&body[pos..], &next_delimiter)
+            .ok_or(MultipartError::Malformed("missing closing boundary"))?;
+        parts.push(parse_part(&body[pos..pos + content_len])?);
+        pos += content_len + next_delimiter.len();
+    }
+}
+
+fn parse_part(raw: &[u8]) -> Result<Part, MultipartError> {
+    let (head, body) = if let Some(body) = raw.strip_prefix(b"\r\n") {
+        (&raw[..0], body)
+    } else {
+        let end =
+            find(raw, b"\r\n\r\n").ok_or(MultipartError::Malformed("unterminated part headers"))?;
+        (&raw[..end], &raw[end + 4..])
+    };
+
+    let head = std::str::from_utf8(head)
+        .map_err(|_| MultipartError::Malformed("part headers are not UTF-8"))?;
+    let mut headers = HeaderMap::default();
+    for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(MultipartError::Malformed("part header without a colon"))?;
+        headers.insert(name.trim(), value);
+    }
+
+    let disposition = headers.get("content-disposition").map(|value| {
+        let params = value.find(';').map_or("", |i| &value[i + 1..]);
+        parse_parameters(params)
+    });
+    let param = |name: &str| {
+        disposition
+            .as_ref()
+            .and_then(|params| params.get(name).cloned())
+    };
+
+    Ok(Part {
+        name: param("name"),
+        filename: param("filename"),
+        content_type: headers.get("content-type").map(str::to_string),
+        headers,
+        body: body.to_vec(),
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD: &[u8] = b"preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Holiday\r\n\
+--XyZ  \r\n\
+Content-Disposition: form-data; name=\"photo\"; filename=\"a;b.png\"\r\n\
+Content-Type: image/png\r\n\
+\r\n\
+\x89PNG\r\n--not-the-boundary\r\n\
+--XyZ--\r\n\
+epilogue";
+
+    #[test]
+    fn splits_fields_and_files() {
+        let parts = parse_multipart(UPLOAD, "XyZ", DEFAULT_MAX_PARTS).unwrap();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].body, b"Holiday");
+
+        assert_eq!(parts[1].name.as_deref(), Some("photo"));
+        assert_eq!(parts[1].filename.as_deref(), Some("a;b.png"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+        assert_eq!(parts[1].body, b"\x89PNG\r\n--not-the-boundary");
+    }
+
+    #[test]
+    fn rejects_broken_framing_and_too_many_parts() {
+        assert_eq!(
+            parse_multipart(UPLOAD, "XyZ", 1).unwrap_err(),
+            MultipartError::TooManyParts
+        );
+        assert_eq!(
+            parse_multipart(b"--XyZ\r\n\r\nunterminated", "XyZ", 8).unwrap_err(),
+            MultipartError::Malformed("missing closing boundary")
+        );
+        assert_eq!(
+            parse_multipart(b"no boundary here", "XyZ", 8).unwrap_err(),
+            MultipartError::Malformed("no opening boundary")
+        );
+        assert_eq!(
+            parse_multipart(b"--XyZ--", "", 8).unwrap_err(),
+            MultipartError::MissingBoundary
+        );
+        assert!(
+            parse_multipart(b"--XyZ--\r\n", "XyZ", 8)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::https::{Request, Response, StatusCode};
+use crate::utils::httpdate;
+
+use super::Router;
+
+/// Where access log lines go. Shared rather than copied by `run_workers`, so lines
+/// from every worker end up in the same place, one whole line at a time.
+pub type AccessLogWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// The default access log writer: stdout, but through `print!`, which the test
+/// harness captures.
+pub(super) struct PrintStdout;
+
+impl Write for PrintStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl Router {
+    /// Writes one Combined Log Format line per answered request to `writer` instead
+    /// of stdout.
+    pub fn set_access_log(&mut self, writer: impl Write + Send + 'static) -> &mut Self {
+        self.access_log = Some(Arc::new(Mutex::new(writer)));
+        self
+    }
+
+    /// Stops writing the access log.
+    pub fn disable_access_log(&mut self) -> &mut Self {
+        self.access_log = None;
+        self.access_log_pending.clear();
+        self
+    }
+
+    /// Logs `resp` as the answer to `req`, or to a request that could not be parsed
+    /// when `req` is `None`. `body_len` is `None` when the size is not known up front.
+    /// The line is only queued; `flush_access_log` writes it out.
+    pub(super) fn log_access(
+        &mut self,
+        peer: Option<SocketAddr>,
+        req: Option<&Request>,
+        resp: &Response,
+        body_len: Option<usize>,
+    ) {
+        if self.access_log.is_none() {
+            return;
+        }
+        let entry = Entry {
+            peer,
+            req,
+            status: resp.status,
+            body_len,
+            request_id: resp.headers.get("x-request-id"),
+        };
+        let line = combined_log_line(&entry, SystemTime::now());
+        self.access_log_pending.push_str(&line);
+    }
+
+    /// Writes the lines queued by `log_access` with one locked write, so the writer is
+    /// touched once per turn of the event loop rather than once per request.
+    pub(super) fn flush_access_log(&mut self) {
+        let Some(writer) = &self.access_log else {
+            return;
+        };
+        if self.access_log_pending.is_empty() {
+            return;
+        }
+        let mut writer = writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let written = writer
+            .write_all(self.access_log_pending.as_bytes())
+            .and_then(|()| writer.flush());
+        if let Err(e) = written {
+            eprintln!("could not write access log: {e}");
+        }
+        self.access_log_pending.clear();
+    }
+}
+
+/// What goes into one access log line.
+struct Entry<'a> {
+    peer: Option<SocketAddr>,
+    req: Option<&'a Request>,
+    status: StatusCode,
+    body_len: Option<usize>,
+    request_id: Option<&'a str>,
+}
+
+/// `host - - [time] "request line" status bytes "referer" "user-agent" "request id"`,
+/// with `-` for anything unknown. The request id goes after the Combined Log Format
+/// fields, where parsers of that format ignore it.
+fn combined_log_line(entry: &Entry, time: SystemTime) -> String {
+    let &Entry {
+        peer,
+        req,
+        status,
+        body_len,
+        request_id,
+    } = entry;
+    let host = peer.map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+    let request_line = req.map_or_else(
+        || "-".to_string(),
+        |req| {
+            let query = if req.query.is_empty() { "" } else { "?" };
+            format!(
+                "{} {}{query}{} {}",
+                req.method.as_str(),
+                req.path,
+                req.query,
+                req.version
+            )
+        },
+    );
+    let bytes = body_len
+        .filter(|&len| len > 0)
+        .map_or_else(|| "-".to_string(), |len| len.to_string());
+    let header = |name| {
+        req.and_then(|req| req.headers.get(name))
+            .map_or_else(|| "-".to_string(), escape)
+    };
+
+    format!(
+        "{host} - - [{}] \"{}\" {} {bytes} \"{}\" \"{}\" \"{}\"\n",
+        httpdate::format_clf(time),
+        escape(&request_line),
+        status.code(),
+        header("referer"),
+        header("user-agent"),
+        request_id.map_or_else(|| "-".to_string(), escape),
+    )
+}
+
+/// Escapes quotes, backslashes and control bytes so a client cannot break a field.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::conn::ParseOptions;
+    use crate::router::request_parsing::parse_request;
+
+    #[test]
+    fn formats_combined_log_lines() {
+        let req = parse_request(
+            b"GET /a%20b?x=1 HTTP/1.1\r\nHost: h\r\nReferer: http://r/\r\nUser-Agent: say \"hi\"\r\n\r\n",
+            b"",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        let peer = "192.0.2.7:5000".parse().ok();
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        let entry = Entry {
+            peer,
+            req: Some(&req),
+            status: StatusCode::Ok,
+            body_len: Some(12),
+            request_id: Some("r-1"),
+        };
+        assert_eq!(
+            combined_log_line(&entry, time),
+            format!(
+                "192.0.2.7 - - [06/Nov/1994:08:49:37 +0000] \"GET {}?x=1 HTTP/1.1\" 200 12 \
+                 \"http://r/\" \"say \\\"hi\\\"\" \"r-1\"\n",
+                req.path
+            )
+        );
+        let entry = Entry {
+            peer: None,
+            req: None,
+            status: StatusCode::BadRequest,
+            body_len: Some(0),
+            request_id: None,
+        };
+        assert_eq!(
+            combined_log_line(&entry, time),
+            "- - - [06/Nov/1994:08:49:37 +0000] \"-\" 400 - \"-\" \"-\" \"-\"\n"
+        );
+    }
+}
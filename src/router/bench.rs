@@ -0,0 +1,59 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::conn::Conn;
+use crate::handlers::error_response;
+
+use super::{ReadOutcome, Router, request_parsing};
+
+/// Outcome of `Router::bench_pipeline`.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineReport {
+    pub iterations: u64,
+    pub elapsed: Duration,
+}
+
+impl PipelineReport {
+    pub fn requests_per_sec(&self) -> f64 {
+        self.iterations as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Router {
+    /// Pushes `raw_request` through framing, `parse_request`, `handle` and `to_bytes`
+    /// `iterations` times without touching a socket, to measure the CPU cost of the
+    /// request pipeline. Sessions created along the way are dropped afterwards.
+    pub fn bench_pipeline(
+        &mut self,
+        local_port: u16,
+        raw_request: &[u8],
+        iterations: u64,
+    ) -> PipelineReport {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let mut conn = Conn::new(local_port, self.parse_options);
+            let resp = match conn.read_outcome(black_box(raw_request)) {
+                ReadOutcome::Ready(parts) => {
+                    match request_parsing::parse_request(
+                        &parts.header_bytes,
+                        &parts.body_bytes,
+                        &self.parse_options,
+                    ) {
+                        Ok(req) => self.handle(local_port, &req),
+                        Err((status, _)) => error_response("HTTP/1.1", status),
+                    }
+                }
+                ReadOutcome::Error { status, .. } => error_response("HTTP/1.1", status),
+                ReadOutcome::Pending => panic!("bench request is incomplete"),
+            };
+            black_box(resp.to_bytes());
+        }
+        let elapsed = start.elapsed();
+
+        self.sessions.clear();
+        PipelineReport {
+            iterations,
+            elapsed,
+        }
+    }
+}
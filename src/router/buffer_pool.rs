@@ -0,0 +1,56 @@
+/// Buffers the pool keeps when they are given back; more are dropped.
+const MAX_POOLED: usize = 4;
+
+/// Read buffers the event loop takes for a `recv` loop and gives back afterwards, so
+/// servicing an event does not allocate a new one. Bytes left in a buffer from an
+/// earlier read are never looked at: only what `recv` just wrote is used.
+#[derive(Debug)]
+pub(super) struct BufferPool {
+    buf_size: usize,
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Hands out buffers of `buf_size` bytes (at least 1).
+    pub(super) fn new(buf_size: usize) -> Self {
+        Self {
+            buf_size: buf_size.max(1),
+            free: Vec::new(),
+        }
+    }
+
+    pub(super) fn take(&mut self) -> Vec<u8> {
+        self.free.pop().unwrap_or_else(|| vec![0u8; self.buf_size])
+    }
+
+    /// Keeps `buf` for the next `take`, unless the pool is full or `buf` is not one
+    /// of its own.
+    pub(super) fn give(&mut self, buf: Vec<u8>) {
+        if buf.len() == self.buf_size && self.free.len() < MAX_POOLED {
+            self.free.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_given_back_buffers_up_to_the_cap() {
+        let mut pool = BufferPool::new(16);
+        let buf = pool.take();
+        assert_eq!(buf.len(), 16);
+        let ptr = buf.as_ptr();
+        pool.give(buf);
+        let reused = pool.take();
+        assert_eq!(reused.as_ptr(), ptr);
+
+        pool.give(vec![0; 8]);
+        assert!(pool.free.is_empty());
+        for _ in 0..MAX_POOLED + 2 {
+            pool.give(vec![0; 16]);
+        }
+        assert_eq!(pool.free.len(), MAX_POOLED);
+    }
+}
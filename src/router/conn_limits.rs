@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use libc::EPOLLIN;
+
+use crate::utils::helpers::{epoll_add, epoll_del};
+
+use super::Router;
+
+impl Router {
+    /// Caps how many connections one client IP may hold open at once (unlimited by
+    /// default). Connections over the cap are closed right after `accept`, before any
+    /// byte is read. With `run_workers` the cap applies per worker.
+    pub fn set_max_conns_per_ip(&mut self, max: usize) -> &mut Self {
+        self.max_conns_per_ip = Some(max);
+        self
+    }
+
+    /// Caps the number of open client connections (unlimited by default). At the cap
+    /// the listeners are taken out of epoll, so new clients wait in the kernel's accept
+    /// backlog until a connection closes.
+    pub fn set_max_total_conns(&mut self, max: usize) -> &mut Self {
+        self.max_total_conns = Some(max);
+        self
+    }
+
+    /// Open connections, counting accepted ones still waiting for registration.
+    fn open_conns(&self) -> usize {
+        self.conns.len() + self.pending_accepts.len()
+    }
+
+    pub(super) fn at_total_conn_cap(&self) -> bool {
+        self.max_total_conns
+            .is_some_and(|max| self.open_conns() >= max)
+    }
+
+    /// Counts a new connection from `ip`, or returns `false` when `ip` is at its cap.
+    pub(super) fn admit_peer(&mut self, ip: IpAddr) -> bool {
+        let active = self.ip_conns.get(&ip).copied().unwrap_or(0);
+        if self.max_conns_per_ip.is_some_and(|max| active >= max) {
+            return false;
+        }
+        *self.ip_conns.entry(ip).or_insert(0) += 1;
+        true
+    }
+
+    /// Stops watching the listeners while at the total connection cap.
+    pub(super) fn pause_accepting(&mut self) {
+        if self.accepting_paused {
+            return;
+        }
+        for &listen_fd in self.listeners.keys() {
+            epoll_del(self.epfd, listen_fd);
+        }
+        self.accepting_paused = true;
+        crate::warn!("connection cap reached, pausing accepts", "open" => self.open_conns());
+    }
+
+    /// Watches the listeners again once connections have dropped below the cap.
+    pub(super) fn resume_accepting_if_room(&mut self) {
+        if !self.accepting_paused || self.at_total_conn_cap() {
+            return;
+        }
+        for &listen_fd in self.listeners.keys() {
+            if let Err(err) = epoll_add(self.epfd, listen_fd, EPOLLIN as u32) {
+                eprintln!("could not resume listener fd={listen_fd}: {err}");
+            }
+        }
+        self.accepting_paused = false;
+    }
+}
+
+pub(super) fn release_ip_conn(ip_conns: &mut HashMap<IpAddr, usize>, ip: IpAddr) {
+    if let Some(count) = ip_conns.get_mut(&ip) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            ip_conns.remove(&ip);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use crate::https::{Body, HeaderMap, HttpMethod, Request, Response, StatusCode};
+
+use super::Router;
+
+/// Cross-origin access rules, applied by the router to requests that carry an `Origin`.
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    /// Origins allowed to read responses, e.g. `https://app.example`, compared
+    /// case-insensitively. `"*"` allows every origin.
+    pub allowed_origins: Vec<String>,
+    /// Methods a preflight may ask for. When empty, the methods of the route the
+    /// preflight targets are allowed.
+    pub allowed_methods: Vec<HttpMethod>,
+    /// Request headers a preflight may ask for beyond the CORS-safelisted ones.
+    pub allowed_headers: Vec<String>,
+    /// Lets the browser send cookies and read the response of credentialed requests.
+    /// The allowed origin is then always echoed, never `*`.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer.
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    /// The `Access-Control-Allow-Origin` value for `origin`, or `None` when it is not
+    /// allowed.
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        let any = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        if any && !self.allow_credentials {
+            return Some("*");
+        }
+        let listed = self
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin));
+        (any || listed).then_some(origin)
+    }
+
+    /// Adds the headers every answer to an allowed cross-origin request carries.
+    fn add_origin_headers(&self, headers: &mut HeaderMap, allow_origin: &str) {
+        headers.insert("Access-Control-Allow-Origin", allow_origin);
+        if allow_origin != "*" {
+            // Caches must not hand this answer to a different origin.
+            headers.append("Vary", "Origin");
+        }
+        if self.allow_credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Router {
+    /// Answers CORS preflights and adds `Access-Control-*` headers to responses for
+    /// allowed origins. Requests from other origins are handled as usual but get no
+    /// CORS headers, so browsers keep their responses from the page.
+    pub fn set_cors(&mut self, config: CorsConfig) -> &mut Self {
+        self.cors = Some(config);
+        self
+    }
+
+    /// A `204` for an `OPTIONS` preflight from an allowed origin that targets a method
+    /// some route on `local_port` accepts at the request's path. Anything else is left
+    /// to normal routing.
+    pub(super) fn cors_preflight(&self, local_port: u16, req: &Request) -> Option<Response> {
+        let cors = self.cors.as_ref()?;
+        if !matches!(req.method, HttpMethod::Options) {
+            return None;
+        }
+        let allow_origin = cors.allow_origin(req.headers.get("origin")?)?;
+        let requested = HttpMethod::from_str(req.headers.get("access-control-request-method")?);
+
+        let routes = self.routes.get(&local_port)?;
+        let route = routes.first_match(&req.path, self.match_options, |route| {
+            route.methods.contains(&requested)
+                || (matches!(requested, HttpMethod::Head)
+                    && route.methods.contains(&HttpMethod::Get))
+        })?;
+        let methods = if cors.allowed_methods.is_empty() {
+            &route.methods
+        } else if cors.allowed_methods.contains(&requested) {
+            &cors.allowed_methods
+        } else {
+            return None;
+        };
+
+        let mut headers = HeaderMap::default();
+        cors.add_origin_headers(&mut headers, allow_origin);
+        let methods: Vec<&str> = methods.iter().map(HttpMethod::as_str).collect();
+        headers.insert("Access-Control-Allow-Methods", &methods.join(", "));
+        if !cors.allowed_headers.is_empty() {
+            headers.insert(
+                "Access-Control-Allow-Headers",
+                &cors.allowed_headers.join(", "),
+            );
+        }
+        if let Some(max_age) = cors.max_age {
+            headers.insert("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+
+        Some(Response {
+            version: req.version.clone(),
+            status: StatusCode::NoContent,
+            headers,
+            body: Body::default(),
+            stream: None,
+            raw: None,
+            file: None,
+        })
+    }
+
+    /// Adds the CORS headers to `resp` when `req` comes from an allowed origin.
+    pub(super) fn apply_cors(&self, req: &Request, resp: &mut Response) {
+        let Some(cors) = &self.cors else {
+            return;
+        };
+        if resp.raw.is_some() || resp.headers.get("access-control-allow-origin").is_some() {
+            return;
+        }
+        if let Some(allow_origin) = req
+            .headers
+            .get("origin")
+            .and_then(|origin| cors.allow_origin(origin))
+        {
+            cors.add_origin_headers(&mut resp.headers, allow_origin);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::handlers::error_response;
+use crate::https::{Request, Response, StatusCode};
+
+use super::{Data, Router};
+
+/// Builds the response for an error the router itself detected. `req` is `None` when
+/// the request could not be parsed.
+pub type ErrorRenderer = Arc<dyn Fn(Option<&Request>, StatusCode) -> Response + Send + Sync>;
+
+impl Router {
+    /// Answers requests on `port` whose path matches no route with `handler` instead
+    /// of the built-in 404, e.g. a branded page or a JSON body chosen by `Accept`. Paths
+    /// that match a route but not its methods still get 405. The handler sees the query,
+    /// body and `Accept` in `Data`, but no path values or session.
+    pub fn set_fallback<H>(&mut self, port: u16, handler: H) -> &mut Self
+    where
+        H: Fn(&Request, &Data) -> Response + Send + Sync + 'static,
+    {
+        self.fallbacks.insert(port, Arc::new(handler));
+        self
+    }
+
+    /// Renders the router's own `status` responses (400, 404, 405, 408, 413, 429 and so
+    /// on) with `renderer` instead of the built-in HTML page. The router still adds the
+    /// headers it relies on, such as `Allow` on a 405 and `Connection: close` after a
+    /// malformed request. Responses returned by handlers are left alone.
+    pub fn set_error_renderer<R>(&mut self, status: StatusCode, renderer: R) -> &mut Self
+    where
+        R: Fn(Option<&Request>, StatusCode) -> Response + Send + Sync + 'static,
+    {
+        self.error_renderers
+            .insert(status.code(), Arc::new(renderer));
+        self
+    }
+
+    /// The router's `status` response, from the registered renderer if there is one.
+    pub(super) fn render_error(&self, req: Option<&Request>, status: StatusCode) -> Response {
+        match self.error_renderers.get(&status.code()) {
+            Some(renderer) => renderer(req, status),
+            None => error_response(req.map_or("HTTP/1.1", |r| r.version.as_str()), status),
+        }
+    }
+}
@@ -1,100 +1,198 @@
 use std::io;
+use std::net::SocketAddr;
 use std::os::fd::RawFd;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
 
-use crate::conn::ConnState;
-use crate::handlers::error_response;
+use crate::conn::{ConnState, FileProgress};
+use crate::https::{HttpMethod, Request, Response, StatusCode};
 use crate::utils::helpers::{
-    accept_nonblocking, close_fd, epoll_add, epoll_del, epoll_mod, epoll_wait_blocking,
+    accept_nonblocking, close_fd, epoll_add, epoll_del, epoll_mod, epoll_wait_timeout,
     recv_nonblocking, send_nonblocking, should_drop,
 };
+use crate::utils::httpdate;
 
-use super::{Conn, IDLE_TIMEOUT, IDLE_TIMEOUT_SECS, ReadOutcome, Router};
+use super::{CloseReason, Conn, ListenAddr, ReadOutcome, Router, UpgradedHandler};
 
 impl Router {
     pub fn handle_connections(&mut self) -> Result<(), io::Error> {
-        let n = epoll_wait_blocking(self.epfd, &mut self.events)?;
+        let result = self.handle_events();
+        self.flush_access_log();
+        result
+    }
+
+    /// One turn of the event loop: waits for events, services them, then expires
+    /// timed-out connections and sessions.
+    fn handle_events(&mut self) -> Result<(), io::Error> {
+        // Queued accepts must not wait for an unrelated event to be registered.
+        let n = if self.pending_accepts.is_empty() {
+            let timeout_ms = self.config.epoll_wait.as_millis().min(i32::MAX as u128) as i32;
+            epoll_wait_timeout(self.epfd, &mut self.events, timeout_ms)?
+        } else {
+            epoll_wait_timeout(self.epfd, &mut self.events, 0)?
+        };
         for i in 0..n {
             let (fd, flags) = {
                 let ev = &self.events[i];
                 (ev.u64 as RawFd, ev.events)
             };
 
-            if let Some(&listen_port) = self.listen_fd_to_port.get(&fd) {
-                self.handle_listen_ready(fd, listen_port)?;
+            if let Some(&listen) = self.listeners.get(&fd) {
+                self.handle_listen_ready(fd, listen)?;
                 continue;
             }
-
-            if should_drop(flags) {
-                self.drop_conn(fd);
+            if self.signal_fd == Some(fd) {
+                self.handle_signal_ready(fd);
                 continue;
             }
 
-            if (flags & (EPOLLIN as u32)) != 0
-                && let Err(e) = self.handle_client_readable(fd)
-            {
-                eprintln!("read error fd={fd}: {e}");
-                self.drop_conn(fd);
-                continue;
-            }
-
-            if (flags & (EPOLLOUT as u32)) == 0 {
-                continue;
-            }
-            let Err(e) = self.handle_client_writable(fd) else {
-                continue;
-            };
-            eprintln!("write error fd={fd}: {e}");
-            self.drop_conn(fd);
-            continue;
+            let started = Instant::now();
+            self.current_route = None;
+            self.handle_client_event(fd, flags);
+            self.check_event_latency(fd, started.elapsed());
         }
 
+        self.register_pending_accepts()?;
+
         let now = Instant::now();
-        let timed_out = self.collect_timed_out_conns(now);
-        for (fd, local_port) in timed_out {
+        let (stalled, idle) = self.collect_timed_out_conns(now);
+        for (fd, local_port) in idle {
             eprintln!(
-                "dropped client connection fd={fd} on port={local_port} after {IDLE_TIMEOUT_SECS}s of inactivity",
+                "dropped client connection fd={fd} on port={local_port} after {:?} of inactivity",
+                self.config.idle_timeout
             );
-            self.drop_conn(fd);
+            self.drop_conn(fd, CloseReason::IdleTimeout);
+        }
+        for fd in stalled {
+            let outcome = ReadOutcome::Error {
+                status: StatusCode::RequestTimeout,
+                reason: format!(
+                    "request stalled for {:?} before it was complete",
+                    self.config.idle_timeout
+                ),
+            };
+            if let Err(e) = self.respond(fd, outcome, false) {
+                eprintln!("could not answer idle timeout fd={fd}: {e}");
+                self.drop_conn(fd, CloseReason::Error);
+            }
+        }
+
+        for fd in self.collect_header_timeouts(now) {
+            let outcome = ReadOutcome::Error {
+                status: StatusCode::RequestTimeout,
+                reason: format!("request head not received within {:?}", self.header_timeout),
+            };
+            if let Err(e) = self.respond(fd, outcome, false) {
+                eprintln!("could not answer header timeout fd={fd}: {e}");
+                self.drop_conn(fd, CloseReason::Error);
+            }
         }
 
-        super::session::cleanup_expired_sessions(&mut self.sessions, now);
+        super::session::cleanup_expired_sessions(&mut self.sessions, now, self.config.session_ttl);
+        self.resume_accepting_if_room();
 
         Ok(())
     }
 
-    fn collect_timed_out_conns(&self, now: Instant) -> Vec<(RawFd, u16)> {
-        let mut timed_out = Vec::new();
-        for (&fd, conn) in &self.conns {
-            if now.duration_since(conn.last_activity) > IDLE_TIMEOUT {
-                timed_out.push((fd, conn.local_port));
+    /// Services one epoll event on a client connection, dropping it on failure.
+    pub(super) fn handle_client_event(&mut self, fd: RawFd, flags: u32) {
+        // A body framed by connection close ends with the peer's half-close, so
+        // EPOLLRDHUP must still go through the read path for those requests.
+        let awaits_eof = (flags & (EPOLLERR as u32)) == 0
+            && self
+                .conns
+                .get(&fd)
+                .is_some_and(|c| c.is_reading_until_close());
+        if should_drop(flags) && !awaits_eof {
+            let reason = if (flags & (EPOLLERR as u32)) != 0 {
+                CloseReason::Error
+            } else {
+                CloseReason::PeerClosed
+            };
+            self.drop_conn(fd, reason);
+            return;
+        }
+
+        if (flags & (EPOLLIN as u32)) != 0
+            && let Err(e) = self.handle_client_readable(fd)
+        {
+            let reason = if e.kind() == io::ErrorKind::UnexpectedEof {
+                CloseReason::PeerClosed
+            } else {
+                eprintln!("read error fd={fd}: {e}");
+                CloseReason::Error
+            };
+            self.drop_conn(fd, reason);
+            return;
+        }
+
+        if (flags & (EPOLLOUT as u32)) != 0
+            && let Err(e) = self.handle_client_writable(fd)
+        {
+            eprintln!("write error fd={fd}: {e}");
+            self.drop_conn(fd, CloseReason::Error);
+        }
+    }
+
+    /// Connections inactive for longer than the idle timeout, split into those stuck
+    /// mid-request, which are owed a `408`, and the rest, which are just closed. The
+    /// stalled ones get another idle period to flush that answer.
+    fn collect_timed_out_conns(&mut self, now: Instant) -> (Vec<RawFd>, Vec<(RawFd, u16)>) {
+        let mut stalled = Vec::new();
+        let mut idle = Vec::new();
+        for (&fd, conn) in &mut self.conns {
+            if now.duration_since(conn.last_activity) <= self.config.idle_timeout {
+                continue;
+            }
+            if conn.is_mid_request() && !conn.close_after_flush {
+                conn.last_activity = now;
+                stalled.push(fd);
+            } else {
+                idle.push((fd, conn.local_port));
             }
         }
-        timed_out
+        (stalled, idle)
     }
 
-    fn handle_listen_ready(&mut self, listen_fd: RawFd, listen_port: u16) -> io::Result<()> {
+    /// Connections still reading a request head past their `header_deadline`.
+    fn collect_header_timeouts(&mut self, now: Instant) -> Vec<RawFd> {
+        let mut expired = Vec::new();
+        for (&fd, conn) in &mut self.conns {
+            if conn.header_deadline.is_some_and(|deadline| now >= deadline)
+                && !conn.close_after_flush
+            {
+                conn.header_deadline = None;
+                expired.push(fd);
+            }
+        }
+        expired
+    }
+
+    fn handle_listen_ready(&mut self, listen_fd: RawFd, listen: ListenAddr) -> io::Result<()> {
+        let listen_port = listen.addr.port();
         loop {
+            if self.at_total_conn_cap() {
+                self.pause_accepting();
+                break;
+            }
             match accept_nonblocking(listen_fd) {
-                Ok(Some(client_fd)) => {
-                    self.conns.insert(
-                        client_fd,
-                        Conn {
-                            local_port: listen_port,
-                            in_buf: Vec::new(),
-                            out_buf: Vec::new(),
-                            state: ConnState::ReadingHeaders,
-                            last_activity: Instant::now(),
-                        },
-                    );
-
-                    let mask = (EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP) as u32;
-                    epoll_add(self.epfd, client_fd, mask)?;
+                Ok(Some((client_fd, Some(peer)))) if !self.admit_peer(peer.ip()) => {
+                    // Counted rather than logged: a flood would otherwise log a line
+                    // per connection.
+                    self.metrics.connection_refused();
+                    close_fd(client_fd);
+                }
+                Ok(Some((client_fd, peer_addr))) if self.accept_batch.is_some() => {
+                    self.pending_accepts
+                        .push_back((client_fd, listen_port, peer_addr));
+                }
+                Ok(Some((client_fd, peer_addr))) => {
+                    self.register_client(client_fd, listen_port, peer_addr)?;
                 }
                 Ok(None) => break,
                 Err(e) => {
+                    self.metrics.accept_error();
                     eprintln!("accept error: {e}");
                     break;
                 }
@@ -103,8 +201,43 @@ impl Router {
         Ok(())
     }
 
+    /// Registers up to `accept_batch` connections queued by `handle_listen_ready`.
+    fn register_pending_accepts(&mut self) -> io::Result<()> {
+        let batch = self.accept_batch.unwrap_or(usize::MAX);
+        for _ in 0..batch {
+            let Some((client_fd, local_port, peer_addr)) = self.pending_accepts.pop_front() else {
+                break;
+            };
+            self.register_client(client_fd, local_port, peer_addr)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn register_client(
+        &mut self,
+        client_fd: RawFd,
+        local_port: u16,
+        peer_addr: Option<SocketAddr>,
+    ) -> io::Result<()> {
+        let mut conn = Conn::new(local_port, self.parse_options);
+        conn.peer_addr = peer_addr;
+        conn.header_deadline = Some(Instant::now() + self.header_timeout);
+        self.conns.insert(client_fd, conn);
+        self.metrics.connection_opened();
+        if let Some(observer) = self.observer.as_mut() {
+            observer.accepted(client_fd, local_port);
+        }
+
+        let mask = self
+            .config
+            .client_mask(EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP);
+        epoll_add(self.epfd, client_fd, mask)
+    }
+
     fn handle_client_writable(&mut self, fd: RawFd) -> io::Result<()> {
         let mut should_close = false;
+        let mut should_upgrade = false;
+        let mut should_reuse = false;
 
         {
             let c = self
@@ -112,86 +245,436 @@ impl Router {
                 .get_mut(&fd)
                 .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
 
-            while !c.out_buf.is_empty() {
-                match send_nonblocking(fd, &c.out_buf)? {
-                    Some(nsent) => {
-                        c.out_buf.drain(..nsent);
-                        c.last_activity = Instant::now();
+            loop {
+                while c.fill_from_stream() {
+                    match send_nonblocking(fd, &c.out_buf)? {
+                        Some(nsent) => {
+                            self.metrics.sent(nsent);
+                            c.out_buf.drain(..nsent);
+                            c.last_activity = Instant::now();
+                        }
+                        None => break,
                     }
-                    None => break,
+                }
+                // A file body goes out only once the head has left `out_buf`.
+                if !c.out_buf.is_empty() || c.file.is_none() {
+                    break;
+                }
+                let before = c.file.as_ref().map_or(0, |file| file.len());
+                let progress = c.send_file(fd)?;
+                // Whatever did not land in `out_buf` went out through `sendfile`.
+                let moved = before - c.file.as_ref().map_or(0, |file| file.len());
+                self.metrics.sent(moved as usize - c.out_buf.len());
+                c.last_activity = Instant::now();
+                if progress != FileProgress::Buffered {
+                    break;
                 }
             }
 
-            if c.out_buf.is_empty() {
-                should_close = true;
+            let flushed = c.out_buf.is_empty() && c.file.is_none();
+            if flushed && c.is_reading_request() {
+                // Only an interim `100 Continue` was queued; go back to reading the body.
+                let mask = self
+                    .config
+                    .client_mask(EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP);
+                epoll_mod(self.epfd, fd, mask)?;
+            } else if flushed {
+                if let Some(observer) = self.observer.as_mut() {
+                    observer.response_flushed(fd);
+                }
+                if let Some(exchange) = self.in_flight.remove(&fd) {
+                    self.completed_exchange = Some(exchange);
+                }
+                match c.state {
+                    _ if c.close_after_flush => should_close = true,
+                    ConnState::Upgraded => {
+                        let mask = self
+                            .config
+                            .client_mask(EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP);
+                        epoll_mod(self.epfd, fd, mask)?;
+                    }
+                    _ if self.upgrades.contains_key(&fd) => should_upgrade = true,
+                    _ => should_reuse = true,
+                }
             }
         }
 
+        if should_upgrade {
+            self.start_upgraded(fd)?;
+        }
+
+        if should_reuse {
+            self.reuse_connection(fd)?;
+        }
+
         if should_close {
-            self.drop_conn(fd);
+            self.drop_conn(fd, CloseReason::Completed);
         }
 
         Ok(())
     }
 
-    fn drop_conn(&mut self, fd: RawFd) {
+    /// Closes the client connection `fd` once any queued response bytes are flushed.
+    /// Returns `false` when `fd` is not (or no longer) a client connection.
+    pub fn close_connection(&mut self, fd: RawFd) -> bool {
+        let Some(c) = self.conns.get_mut(&fd) else {
+            return false;
+        };
+
+        self.upgrades.remove(&fd);
+        if c.out_buf.is_empty() && c.file.is_none() {
+            self.drop_conn(fd, CloseReason::Requested);
+            return true;
+        }
+
+        c.close_after_flush = true;
+        c.state = ConnState::Responding;
+        let mask = self.config.client_mask(EPOLLOUT | EPOLLERR | EPOLLHUP);
+        if let Err(e) = epoll_mod(self.epfd, fd, mask) {
+            eprintln!("could not arm close for fd={fd}: {e}");
+            self.drop_conn(fd, CloseReason::Error);
+        }
+        true
+    }
+
+    /// Routes a parsed request that arrived on connection `fd`.
+    fn dispatch(
+        &mut self,
+        fd: RawFd,
+        local_port: u16,
+        req: &Request,
+    ) -> (Response, Option<Box<dyn UpgradedHandler>>) {
+        if !self.host_allowed(req) {
+            eprintln!("request rejected: unexpected Host fd={fd}");
+            return (self.render_error(Some(req), StatusCode::BadRequest), None);
+        }
+        if let Some(rejection) = self.admit_session(fd, req) {
+            return (rejection, None);
+        }
+        self.current_peer = self.conns.get(&fd).and_then(|c| c.peer_addr);
+        let outcome = match self.handle_upgrade(local_port, req) {
+            Some((resp, protocol)) => (resp, protocol),
+            None => (self.handle(local_port, req), None),
+        };
+        self.current_peer = None;
+        outcome
+    }
+
+    pub(super) fn drop_conn(&mut self, fd: RawFd, reason: CloseReason) {
         epoll_del(self.epfd, fd);
-        self.conns.remove(&fd);
+        let Some(conn) = self.conns.remove(&fd) else {
+            close_fd(fd);
+            return;
+        };
+        if let Some(sid) = conn.session_id {
+            super::session::release_session_conn(&mut self.session_conns, &sid);
+        }
+        if let Some(peer) = conn.peer_addr {
+            super::conn_limits::release_ip_conn(&mut self.ip_conns, peer.ip());
+        }
+        self.metrics.connection_closed();
+        if let Some(observer) = self.observer.as_mut() {
+            observer.closed(fd, reason);
+        }
+        self.upgrades.remove(&fd);
+        self.in_flight.remove(&fd);
         close_fd(fd);
     }
 
     fn handle_client_readable(&mut self, fd: RawFd) -> io::Result<()> {
-        let mut buf = [0u8; 4096];
+        let mut buf = self.read_buffers.take();
+        let result = self.read_client(fd, &mut buf);
+        self.read_buffers.give(buf);
+        result
+    }
+
+    fn read_client(&mut self, fd: RawFd, buf: &mut [u8]) -> io::Result<()> {
+        if self
+            .conns
+            .get(&fd)
+            .is_some_and(|c| matches!(c.state, ConnState::Upgraded))
+        {
+            return self.handle_upgraded_readable(fd, buf);
+        }
 
         loop {
-            match recv_nonblocking(fd, &mut buf)? {
+            let mut peer_closed = false;
+            let outcome = match recv_nonblocking(fd, buf)? {
                 Some(0) => {
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed"));
+                    let c = self
+                        .conns
+                        .get_mut(&fd)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+                    let Some(outcome) = c.finish_at_eof() else {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed"));
+                    };
+                    peer_closed = true;
+                    if let (Some(observer), ReadOutcome::Ready(parts)) =
+                        (self.observer.as_mut(), &outcome)
+                    {
+                        observer.body_complete(fd, parts.body_bytes.len());
+                    }
+                    outcome
                 }
                 Some(nread) => {
-                    let outcome = {
-                        let c = self.conns.get_mut(&fd).ok_or_else(|| {
-                            io::Error::new(io::ErrorKind::NotFound, "conn missing")
-                        })?;
-                        c.last_activity = Instant::now();
-                        c.read_outcome(&buf[..nread])
-                    };
+                    let c = self
+                        .conns
+                        .get_mut(&fd)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+                    c.last_activity = Instant::now();
+                    self.metrics.received(nread);
+                    self.read_request_bytes(fd, &buf[..nread])?
+                }
+                None => break,
+            };
 
-                    let response = match outcome {
-                        ReadOutcome::Pending => continue,
-                        ReadOutcome::Ready(parts) => {
-                            match super::request_parsing::parse_request(
-                                &parts.header_bytes,
-                                &parts.body_bytes,
-                            ) {
-                                Ok(req) => self.handle(parts.local_port, &req),
-                                Err((status, reason)) => {
-                                    eprintln!("request rejected: {reason}");
-                                    error_response("HTTP/1.1", status)
-                                }
-                            }
-                        }
-                        ReadOutcome::Error { status, reason } => {
-                            eprintln!("request rejected: {reason}");
-                            error_response("HTTP/1.1", status)
-                        }
-                    };
+            if matches!(outcome, ReadOutcome::Pending) {
+                continue;
+            }
+            return self.respond(fd, outcome, peer_closed);
+        }
+
+        // The parser may have queued a `100 Continue` while the body is still on its way.
+        if self
+            .conns
+            .get(&fd)
+            .is_some_and(|c| !c.out_buf.is_empty() && c.is_reading_request())
+        {
+            let mask = self
+                .config
+                .client_mask(EPOLLIN | EPOLLRDHUP | EPOLLOUT | EPOLLERR | EPOLLHUP);
+            epoll_mod(self.epfd, fd, mask)?;
+        }
+        Ok(())
+    }
+
+    /// Feeds received bytes to the connection's parser, telling the lifecycle observer
+    /// when the request head and the whole request have arrived.
+    fn read_request_bytes(&mut self, fd: RawFd, bytes: &[u8]) -> io::Result<ReadOutcome> {
+        let c = self
+            .conns
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+        let was_reading_headers = matches!(c.state, ConnState::ReadingHeaders);
+        let outcome = c.read_outcome(bytes);
+
+        let head_pending =
+            matches!(c.state, ConnState::ReadingHeaders) && matches!(outcome, ReadOutcome::Pending);
+        if !head_pending {
+            c.header_deadline = None;
+        } else if c.header_deadline.is_none() && !c.in_buf.is_empty() {
+            c.header_deadline = Some(Instant::now() + self.header_timeout);
+        }
+
+        if let Some(observer) = self.observer.as_mut() {
+            let ready = matches!(outcome, ReadOutcome::Ready(_));
+            if was_reading_headers && (ready || !matches!(c.state, ConnState::ReadingHeaders)) {
+                observer.headers_complete(fd);
+            }
+            if let ReadOutcome::Ready(parts) = &outcome {
+                observer.body_complete(fd, parts.body_bytes.len());
+            }
+        }
+        Ok(outcome)
+    }
 
+    /// Answers a completed (or rejected) request and arms the fd for writing.
+    fn respond(&mut self, fd: RawFd, outcome: ReadOutcome, peer_closed: bool) -> io::Result<()> {
+        let mut upgrade = None;
+        let mut handled = None;
+        let mut response = match outcome {
+            ReadOutcome::Pending => return Ok(()),
+            ReadOutcome::Ready(parts) => {
+                match super::request_parsing::parse_request(
+                    &parts.header_bytes,
+                    &parts.body_bytes,
+                    &self.parse_options,
+                ) {
+                    Ok(mut req) => {
+                        req.trailers = parts.trailers;
+                        let (resp, protocol) = self.dispatch(fd, parts.local_port, &req);
+                        upgrade = protocol;
+                        handled = Some(req);
+                        resp
+                    }
+                    Err((status, reason)) => {
+                        eprintln!("request rejected: {reason}");
+                        self.render_error(None, status)
+                    }
+                }
+            }
+            ReadOutcome::Error { status, reason } => {
+                eprintln!("request rejected: {reason}");
+                self.render_error(None, status)
+            }
+        };
+
+        let size = match (&response.raw, &response.file) {
+            (Some(raw), _) => raw.len(),
+            (None, Some(file)) => file.len() as usize,
+            (None, None) => response.body.len(),
+        };
+        if let Some(max) = self.max_response_body
+            && size > max
+        {
+            crate::error!(
+                "handler response exceeds the maximum body size",
+                "size" => size,
+                "max" => max
+            );
+            response = self.render_error(handled.as_ref(), StatusCode::InternalServerError);
+        }
+
+        let c = self
+            .conns
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+        // HEAD answers carry the headers a GET would get, including its Content-Length,
+        // but never the body itself.
+        let head_only = response.raw.is_none()
+            && handled
+                .as_ref()
+                .is_some_and(|req| matches!(req.method, HttpMethod::Head));
+        c.stream = response.take_stream().filter(|_| !head_only);
+        c.file = response.file.take().filter(|_| !head_only);
+        c.stream_cap = self.max_response_body;
+        let peer = c.peer_addr;
+        let body_len = match (&response.raw, &c.file) {
+            _ if head_only => Some(0),
+            (Some(raw), _) => Some(raw.len()),
+            (None, Some(file)) => Some(file.len() as usize),
+            (None, None) if c.stream.is_some() => None,
+            (None, None) => Some(response.body.len()),
+        };
+        if response.raw.is_some() {
+            c.close_after_flush = true;
+        } else if handled.is_none() {
+            // A request that failed to parse leaves the framing of what follows unknown,
+            // so whatever the peer sent after it is never treated as another request.
+            response.headers.insert("Connection", "close");
+            c.close_after_flush = true;
+            c.in_buf.clear();
+        } else if upgrade.is_none() {
+            let keep_alive = self.keep_alive
+                && !peer_closed
+                && handled.as_ref().is_some_and(wants_keep_alive)
+                && !has_close_token(response.headers.get("connection"));
+            response.headers.insert(
+                "Connection",
+                if keep_alive { "keep-alive" } else { "close" },
+            );
+            c.close_after_flush |= !keep_alive;
+        }
+
+        if response.raw.is_none() {
+            if response.headers.get("date").is_none() {
+                response
+                    .headers
+                    .insert("Date", &httpdate::format(SystemTime::now()));
+            }
+            if let Some(name) = &self.server_name
+                && response.headers.get("server").is_none()
+            {
+                response.headers.insert("Server", name);
+            }
+        }
+
+        let bytes = if head_only || c.stream.is_some() || c.file.is_some() {
+            response.head_bytes()
+        } else {
+            response.to_bytes()
+        };
+        c.out_buf.extend_from_slice(&bytes);
+        c.state = ConnState::Responding;
+        if let Some(observer) = self.observer.as_mut() {
+            observer.response_queued(fd, response.status);
+        }
+        self.metrics.response(response.status);
+        self.log_access(peer, handled.as_ref(), &response, body_len);
+        if self.capture_exchanges
+            && let Some(req) = handled
+        {
+            self.in_flight.insert(fd, (req, response));
+        }
+        if let Some(protocol) = upgrade {
+            self.upgrades.insert(fd, protocol);
+        }
+
+        // A half-closed peer keeps the fd readable forever; only wait for writability.
+        let mut events = EPOLLOUT | EPOLLERR | EPOLLHUP;
+        if !peer_closed {
+            events |= EPOLLIN | EPOLLRDHUP;
+        }
+        epoll_mod(self.epfd, fd, self.config.client_mask(events))
+    }
+
+    /// Readies a kept-alive connection for its next request, answering right away if
+    /// the client already pipelined one into `in_buf`.
+    fn reuse_connection(&mut self, fd: RawFd) -> io::Result<()> {
+        let c = self
+            .conns
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+        c.state = ConnState::ReadingHeaders;
+        c.header_deadline = None;
+        c.out_buf.clear();
+
+        let outcome = self.read_request_bytes(fd, &[])?;
+        if !matches!(outcome, ReadOutcome::Pending) {
+            return self.respond(fd, outcome, false);
+        }
+        let mut events = EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP;
+        if self.conns.get(&fd).is_some_and(|c| !c.out_buf.is_empty()) {
+            events |= EPOLLOUT;
+        }
+        epoll_mod(self.epfd, fd, self.config.client_mask(events))
+    }
+
+    fn handle_upgraded_readable(&mut self, fd: RawFd, buf: &mut [u8]) -> io::Result<()> {
+        let mut received = false;
+        loop {
+            match recv_nonblocking(fd, buf)? {
+                Some(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed"));
+                }
+                Some(nread) => {
                     let c = self
                         .conns
                         .get_mut(&fd)
                         .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
-                    c.out_buf.extend_from_slice(&response.to_bytes());
-                    c.state = ConnState::Responding;
-
-                    let mask = (EPOLLIN | EPOLLOUT | EPOLLRDHUP | EPOLLERR | EPOLLHUP) as u32;
-                    epoll_mod(self.epfd, fd, mask)?;
-                    break;
+                    c.last_activity = Instant::now();
+                    self.metrics.received(nread);
+                    c.in_buf.extend_from_slice(&buf[..nread]);
+                    received = true;
                 }
                 None => break,
             }
         }
 
+        if received {
+            self.drive_upgraded(fd)?;
+        }
         Ok(())
     }
 }
+
+/// HTTP/1.1 connections persist unless the client says `close`; HTTP/1.0 ones only
+/// when the client explicitly asks for `keep-alive`.
+fn wants_keep_alive(req: &Request) -> bool {
+    let connection = req.headers.get("connection");
+    if has_close_token(connection) {
+        return false;
+    }
+    req.version == "HTTP/1.1"
+        || connection.is_some_and(|v| {
+            v.split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("keep-alive"))
+        })
+}
+
+fn has_close_token(connection: Option<&str>) -> bool {
+    connection.is_some_and(|v| {
+        v.split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("close"))
+    })
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::https::{HttpMethod, Request, Response};
+
+use super::{Data, Middleware, Next, Route, Router};
+
+/// Registers routes on one port under a shared path prefix and middleware; get one
+/// from `Router::group`. The routes it adds are ordinary routes with the prefix
+/// written out, so they match and take priority exactly as if added directly.
+pub struct RouteGroup<'a> {
+    router: &'a mut Router,
+    port: u16,
+    prefix: String,
+    middleware: Vec<Middleware>,
+    /// Indexes of the routes added through this group or the groups nested in it.
+    members: Vec<usize>,
+    /// The enclosing group's `members`, which learn about ours when we are dropped.
+    parent_members: Option<&'a mut Vec<usize>>,
+}
+
+impl Router {
+    /// Starts a group of routes on `port` whose patterns all begin with `prefix`.
+    pub fn group(&mut self, port: u16, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup {
+            router: self,
+            port,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            middleware: Vec::new(),
+            members: Vec::new(),
+            parent_members: None,
+        }
+    }
+}
+
+impl RouteGroup<'_> {
+    /// Registers `handler` for `methods` on the group's prefix followed by `pattern`,
+    /// wrapped in the group's middleware. An empty `pattern` is the prefix itself.
+    pub fn add_route<H>(&mut self, pattern: &str, methods: Vec<HttpMethod>, handler: H) -> &mut Self
+    where
+        H: Fn(&Request, &Data) -> Response + Send + Sync + 'static,
+    {
+        let pattern = self.full_pattern(pattern);
+        let index = self
+            .router
+            .push_route(self.port, &pattern, methods, Arc::new(handler));
+        self.route_mut(index).middleware = self.middleware.clone();
+        self.members.push(index);
+        self
+    }
+
+    /// Wraps every route of the group, including the ones already added and those of
+    /// nested groups, in `middleware`. It runs inside global middleware and any
+    /// middleware the group's routes got before it.
+    pub fn add_middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&Request, &Data, Next<'_>) -> Response + Send + Sync + 'static,
+    {
+        let middleware: Middleware = Arc::new(middleware);
+        for index in self.members.clone() {
+            self.route_mut(index)
+                .middleware
+                .push(Arc::clone(&middleware));
+        }
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// A group nested in this one: its prefix follows ours and its routes get our
+    /// middleware first.
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup {
+            prefix: self.full_pattern(prefix).trim_end_matches('/').to_string(),
+            router: &mut *self.router,
+            port: self.port,
+            middleware: self.middleware.clone(),
+            members: Vec::new(),
+            parent_members: Some(&mut self.members),
+        }
+    }
+
+    fn full_pattern(&self, pattern: &str) -> String {
+        if pattern.is_empty() || pattern.starts_with('/') {
+            format!("{}{pattern}", self.prefix)
+        } else {
+            format!("{}/{pattern}", self.prefix)
+        }
+    }
+
+    fn route_mut(&mut self, index: usize) -> &mut Route {
+        self.router
+            .routes
+            .get_mut(&self.port)
+            .expect("the group's port has its routes")
+            .route_mut(index)
+    }
+}
+
+impl Drop for RouteGroup<'_> {
+    fn drop(&mut self) {
+        if let Some(parent_members) = self.parent_members.as_mut() {
+            parent_members.append(&mut self.members);
+        }
+    }
+}
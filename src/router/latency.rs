@@ -0,0 +1,56 @@
+use std::os::fd::RawFd;
+use std::time::Duration;
+
+use super::Router;
+
+/// One client event that held the event loop longer than the configured threshold.
+/// Every other connection waits while a single event is serviced, so these mark
+/// head-of-line blocking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowEvent {
+    pub fd: RawFd,
+    pub elapsed: Duration,
+    /// Pattern of the route whose handler ran during the event, if any.
+    pub route: Option<String>,
+}
+
+pub type SlowEventHook = Box<dyn FnMut(&SlowEvent)>;
+
+impl Router {
+    /// Reports client events that take longer than `threshold` to service. They are
+    /// logged as warnings unless a hook is installed with `on_slow_event`.
+    pub fn set_slow_event_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.slow_event_threshold = Some(threshold);
+        self
+    }
+
+    /// Hands slow events to `hook` instead of logging them.
+    pub fn on_slow_event(&mut self, hook: impl FnMut(&SlowEvent) + 'static) -> &mut Self {
+        self.slow_event_hook = Some(Box::new(hook));
+        self
+    }
+
+    pub(super) fn check_event_latency(&mut self, fd: RawFd, elapsed: Duration) {
+        if self
+            .slow_event_threshold
+            .is_none_or(|threshold| elapsed <= threshold)
+        {
+            return;
+        }
+
+        let event = SlowEvent {
+            fd,
+            elapsed,
+            route: self.current_route.take(),
+        };
+        match self.slow_event_hook.as_mut() {
+            Some(hook) => hook(&event),
+            None => crate::warn!(
+                "event loop blocked by a slow event",
+                "fd" => event.fd,
+                "elapsed_ms" => event.elapsed.as_millis(),
+                "route" => event.route.as_deref().unwrap_or("-")
+            ),
+        }
+    }
+}
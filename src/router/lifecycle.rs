@@ -0,0 +1,32 @@
+use std::os::fd::RawFd;
+
+use crate::https::StatusCode;
+
+/// Hooks the router calls as a client connection moves through its lifecycle, for
+/// tracing where a connection stalls. Every method defaults to doing nothing.
+pub trait ConnLifecycleObserver {
+    fn accepted(&mut self, _fd: RawFd, _local_port: u16) {}
+    /// The request head (request line and headers) has been fully received.
+    fn headers_complete(&mut self, _fd: RawFd) {}
+    /// The whole request, body included, has been received.
+    fn body_complete(&mut self, _fd: RawFd, _body_len: usize) {}
+    fn response_queued(&mut self, _fd: RawFd, _status: StatusCode) {}
+    /// Everything queued for the connection has been written to the socket.
+    fn response_flushed(&mut self, _fd: RawFd) {}
+    fn closed(&mut self, _fd: RawFd, _reason: CloseReason) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The exchange finished and the connection was not kept alive.
+    Completed,
+    /// The client hung up.
+    PeerClosed,
+    IdleTimeout,
+    /// `Router::close_connection` was called.
+    Requested,
+    /// `Router::drain` reached its deadline before the response was flushed.
+    DrainTimeout,
+    /// A socket or epoll error.
+    Error,
+}
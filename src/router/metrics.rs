@@ -0,0 +1,185 @@
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::https::{HttpMethod, StatusCode, response_with_body};
+
+use super::Router;
+
+/// `Content-Type` of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counters behind `Router::metrics`. `run_workers` hands every worker the same set,
+/// so a snapshot of the configuring router covers all of them.
+#[derive(Debug, Default)]
+pub(super) struct Counters {
+    requests: AtomicU64,
+    /// Indexed by status class, `1xx` first.
+    responses: [AtomicU64; 5],
+    active_connections: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    accept_errors: AtomicU64,
+    refused_connections: AtomicU64,
+}
+
+/// A point-in-time copy of the router's counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Responses queued, including the ones for requests that failed to parse.
+    pub requests: u64,
+    pub responses_1xx: u64,
+    pub responses_2xx: u64,
+    pub responses_3xx: u64,
+    pub responses_4xx: u64,
+    pub responses_5xx: u64,
+    /// Client connections open right now.
+    pub active_connections: u64,
+    /// Bytes received from clients, upgraded protocols included.
+    pub bytes_in: u64,
+    /// Bytes written to clients, file bodies sent with `sendfile` included.
+    pub bytes_out: u64,
+    /// `accept` calls that failed.
+    pub accept_errors: u64,
+    /// Connections closed right after `accept` because their address was at the
+    /// per-IP cap.
+    pub refused_connections: u64,
+}
+
+impl Counters {
+    pub(super) fn response(&self, status: StatusCode) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        // Codes below 100 or above 599 are counted with the nearest class.
+        let class = (status.code() / 100).clamp(1, 5) as usize - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn connection_opened(&self) {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn accept_error(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn connection_refused(&self) {
+        self.refused_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Metrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Metrics {
+            requests: load(&self.requests),
+            responses_1xx: load(&self.responses[0]),
+            responses_2xx: load(&self.responses[1]),
+            responses_3xx: load(&self.responses[2]),
+            responses_4xx: load(&self.responses[3]),
+            responses_5xx: load(&self.responses[4]),
+            active_connections: load(&self.active_connections),
+            bytes_in: load(&self.bytes_in),
+            bytes_out: load(&self.bytes_out),
+            accept_errors: load(&self.accept_errors),
+            refused_connections: load(&self.refused_connections),
+        }
+    }
+}
+
+impl Metrics {
+    /// The counters in the Prometheus text exposition format, each with its `# HELP`
+    /// and `# TYPE` lines.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{labels} {value}");
+            }
+        };
+
+        family(
+            "http_requests_total",
+            "counter",
+            "Responses queued, including those to unparseable requests.",
+            &[("", self.requests)],
+        );
+        family(
+            "http_responses_total",
+            "counter",
+            "Responses queued, by status class.",
+            &[
+                ("{class=\"1xx\"}", self.responses_1xx),
+                ("{class=\"2xx\"}", self.responses_2xx),
+                ("{class=\"3xx\"}", self.responses_3xx),
+                ("{class=\"4xx\"}", self.responses_4xx),
+                ("{class=\"5xx\"}", self.responses_5xx),
+            ],
+        );
+        family(
+            "http_active_connections",
+            "gauge",
+            "Client connections currently open.",
+            &[("", self.active_connections)],
+        );
+        family(
+            "http_received_bytes_total",
+            "counter",
+            "Bytes received from clients.",
+            &[("", self.bytes_in)],
+        );
+        family(
+            "http_sent_bytes_total",
+            "counter",
+            "Bytes written to clients.",
+            &[("", self.bytes_out)],
+        );
+        family(
+            "http_accept_errors_total",
+            "counter",
+            "Failed accept calls.",
+            &[("", self.accept_errors)],
+        );
+        family(
+            "http_refused_connections_total",
+            "counter",
+            "Connections closed at accept by the per-IP cap.",
+            &[("", self.refused_connections)],
+        );
+        out
+    }
+}
+
+impl Router {
+    /// Serves `metrics` in the Prometheus format at `GET /metrics` on `port`.
+    pub(super) fn add_metrics_route(&mut self, port: u16) {
+        let counters = Arc::clone(&self.metrics);
+        self.add_route(
+            port,
+            "/metrics",
+            vec![HttpMethod::Get],
+            move |req, _data| {
+                let body = counters.snapshot().to_prometheus().into_bytes();
+                response_with_body(&req.version, StatusCode::Ok, PROMETHEUS_CONTENT_TYPE, body)
+            },
+        );
+    }
+
+    /// Current values of the request, connection and traffic counters. The counters
+    /// are read one at a time, so a snapshot taken while workers are serving may be
+    /// slightly inconsistent between fields.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot()
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use crate::https::{Request, Response};
+
+use super::route_tree::RouteTable;
+use super::{Data, Handler, Router};
+
+/// Wraps route handlers. A middleware either answers by itself (e.g. rejecting an
+/// unauthenticated request) or calls `next.run` and may adjust the response it gets.
+pub type Middleware = Arc<dyn Fn(&Request, &Data, Next<'_>) -> Response + Send + Sync>;
+
+/// The rest of a middleware chain: the middleware still to run, then the handler.
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    handler: &'a Handler,
+}
+
+impl<'a> Next<'a> {
+    pub(super) fn new(middleware: &'a [Middleware], handler: &'a Handler) -> Self {
+        Self {
+            middleware,
+            handler,
+        }
+    }
+
+    /// Runs the remaining middleware and then the route handler.
+    pub fn run(self, req: &Request, data: &Data) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first(req, data, Next::new(rest, self.handler)),
+            None => (self.handler)(req, data),
+        }
+    }
+}
+
+impl Router {
+    /// Wraps every route handler, on every port, in `middleware`. Global middleware
+    /// runs outermost, in the order added, followed by the route's own middleware.
+    /// It only sees requests that matched a route: 404s, 405s and requests answered by
+    /// the global preprocessor never reach it.
+    pub fn add_middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&Request, &Data, Next<'_>) -> Response + Send + Sync + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Wraps the handlers of the routes registered on `port` with exactly `pattern` in
+    /// `middleware`, inside any global middleware and any middleware they already
+    /// have. Returns `false` when no such route exists.
+    pub fn add_route_middleware<M>(&mut self, port: u16, pattern: &str, middleware: M) -> bool
+    where
+        M: Fn(&Request, &Data, Next<'_>) -> Response + Send + Sync + 'static,
+    {
+        let middleware: Middleware = Arc::new(middleware);
+        let mut found = false;
+        for route in self
+            .routes
+            .get_mut(&port)
+            .into_iter()
+            .flat_map(RouteTable::routes_mut)
+        {
+            if route.pattern == pattern {
+                route.middleware.push(Arc::clone(&middleware));
+                found = true;
+            }
+        }
+        found
+    }
+}
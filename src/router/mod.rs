@@ -1,54 +1,400 @@
-use crate::log;
-use std::collections::HashMap;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
 use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::os::fd::RawFd;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use libc::{EPOLLIN, epoll_event};
+use libc::{EPOLLET, EPOLLIN, epoll_event};
+use rand::RngCore;
+use rand::rngs::OsRng;
 
-use crate::conn::Conn;
-use crate::handlers::error_response;
-use crate::https::{HttpMethod, Request, Response, StatusCode};
+use crate::conn::{AbsoluteFormPolicy, Conn, ParseOptions};
+use crate::handlers::assets::{static_asset_handler, static_dir_handler};
+use crate::handlers::conditional::{etag_for, if_none_match_matches, not_modified};
+use crate::https::{
+    CharsetError, CompressionFilter, ContentType, HeaderMap, HttpMethod, LineEnding, Request,
+    Response, StatusCode, accepts_gzip, decode_text, parse_accept, redirect,
+};
 use crate::info;
 use crate::utils::helpers::create_epoll;
 use crate::utils::helpers::{close_fd, create_listen_socket, epoll_add};
 
+mod access_log;
+#[cfg(feature = "bench")]
+mod bench;
+mod buffer_pool;
+mod conn_limits;
+mod cors;
+mod errors;
 mod event_loop;
+mod group;
+mod latency;
+mod lifecycle;
+mod metrics;
+mod middleware;
+mod params;
+mod request_id;
 mod request_parsing;
 mod route_matching;
+mod route_tree;
+mod segment_regex;
 mod session;
+mod shutdown;
+mod upgrade;
+mod workers;
 
-const IDLE_TIMEOUT_SECS: u64 = 10;
-const IDLE_TIMEOUT: Duration = Duration::from_secs(IDLE_TIMEOUT_SECS);
+pub use crate::utils::helpers::SocketConfig;
+pub use access_log::AccessLogWriter;
+#[cfg(feature = "bench")]
+pub use bench::PipelineReport;
+pub use cors::CorsConfig;
+pub use errors::ErrorRenderer;
+pub use group::RouteGroup;
+pub use latency::{SlowEvent, SlowEventHook};
+pub use lifecycle::{CloseReason, ConnLifecycleObserver};
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Next};
+pub use params::ParamError;
+pub use route_matching::TrailingSlash;
+pub(crate) use route_matching::parse_query;
+pub use upgrade::{
+    UpgradeOutcome, UpgradeRoute, UpgradeRouteHandler, UpgradeStatus, UpgradedHandler,
+    switching_protocols,
+};
 
-const SESSION_TTL_SECS: u64 = 60 * 30;
-const SESSION_TTL: Duration = Duration::from_secs(SESSION_TTL_SECS);
+use access_log::PrintStdout;
+use buffer_pool::BufferPool;
+use metrics::Counters;
+use route_matching::MatchOptions;
+use route_tree::RouteTable;
 
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A route handler. Closures may capture state (a connection pool, config) as long
+/// as it is `Send + Sync`; plain `fn` items work too.
 pub type Handler = Arc<dyn Fn(&Request, &Data) -> Response + Send + Sync>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Data {
     pub path_value: HashMap<String, String>,
+    /// First value of each query parameter; see `query_all` for repeated keys.
     pub query_value: HashMap<String, String>,
     pub session_id: Option<String>,
     pub is_new_session: bool,
     pub body: Vec<u8>,
+    deadline: Option<Instant>,
+    accept: Vec<(String, f32)>,
+    content_type: Option<ContentType>,
+    query_multi: HashMap<String, Vec<String>>,
+    peer_addr: Option<SocketAddr>,
+    session_data: HashMap<String, String>,
+    session_writes: RefCell<Vec<SessionWrite>>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    request_id: Option<String>,
+}
+
+/// A change to the session's `data` queued by a handler through `Data`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SessionWrite {
+    Set(String, String),
+    Remove(String),
+}
+
+impl Data {
+    /// Data carrying only the parsed query string.
+    fn with_query(query: &str) -> Self {
+        let query_multi = route_matching::parse_query(query);
+        Self {
+            query_value: query_multi
+                .iter()
+                .map(|(key, values)| (key.clone(), values[0].clone()))
+                .collect(),
+            query_multi,
+            ..Default::default()
+        }
+    }
+
+    /// Point in time by which the handler should have answered, when the router has a
+    /// request budget configured. Nothing enforces it; handlers check it cooperatively.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Time left before the deadline (zero once it has passed).
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Address of the client that sent the request; `None` for connections that are not
+    /// over IP (and for requests passed to `Router::handle` directly).
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Id of the request, echoed in its `X-Request-Id` response header: the client's own
+    /// when it sent a well-formed one, otherwise generated. `None` in upgrade handlers.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// The application state installed with `Router::set_state`, if it is a `T`.
+    pub fn state<T: Any>(&self) -> Option<&T> {
+        self.state.as_deref()?.downcast_ref()
+    }
+
+    /// Media ranges from the request's `Accept` header, most preferred first.
+    pub fn accept(&self) -> &[(String, f32)] {
+        &self.accept
+    }
+
+    /// Every value given for query parameter `key`, in order (`?tag=a&tag=b`).
+    pub fn query_all(&self, key: &str) -> &[String] {
+        self.query_multi.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// The request's `Content-Type`, parsed once for handlers and internal code alike.
+    pub fn content_type(&self) -> Option<&ContentType> {
+        self.content_type.as_ref()
+    }
+
+    /// Value stored under `key` in the session, as it was when the handler was called.
+    pub fn session_value(&self, key: &str) -> Option<&str> {
+        self.session_data.get(key).map(String::as_str)
+    }
+
+    /// Snapshot of the session's `data` taken before the handler was called.
+    pub fn session_data(&self) -> &HashMap<String, String> {
+        &self.session_data
+    }
+
+    /// Asks the router to store `value` under `key` in the session.
+    ///
+    /// Handlers only get `&Data`, so session writes are queued rather than applied:
+    /// the router applies them in order once the handler has returned, whatever the
+    /// response. Until then `session_value` keeps returning the snapshot. Requests
+    /// that run no route handler have no session, and their writes are dropped.
+    pub fn set_session_value(&self, key: &str, value: &str) {
+        self.session_writes
+            .borrow_mut()
+            .push(SessionWrite::Set(key.to_string(), value.to_string()));
+    }
+
+    /// Asks the router to remove `key` from the session; see `set_session_value`.
+    pub fn remove_session_value(&self, key: &str) {
+        self.session_writes
+            .borrow_mut()
+            .push(SessionWrite::Remove(key.to_string()));
+    }
+
+    /// The body decoded to UTF-8 according to the `charset` of its `Content-Type`.
+    /// `body` keeps the raw bytes when the charset is unknown or the bytes are invalid.
+    pub fn body_text(&self) -> Result<String, CharsetError> {
+        let charset = self
+            .content_type
+            .as_ref()
+            .and_then(|ct| ct.param("charset"));
+        decode_text(&self.body, charset)
+    }
 }
 
+/// Runs before routing on every port; returning a response answers the request
+/// without consulting any route. It sees no path values or session.
+pub type Preprocessor = Arc<dyn Fn(&Request, &Data) -> Option<Response> + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Route {
     pub methods: Vec<HttpMethod>,
     pub pattern: String,
     pub handler: Handler,
+    /// Runs around `handler`, outermost first; see `Router::add_route_middleware`.
+    pub middleware: Vec<Middleware>,
+}
+
+/// One row of a route table for `Router::add_routes`.
+pub struct RouteSpec<'a> {
+    pub port: u16,
+    pub pattern: &'a str,
+    pub methods: &'a [HttpMethod],
+    pub handler: Handler,
+}
+
+impl<'a> RouteSpec<'a> {
+    pub fn new<H>(port: u16, pattern: &'a str, methods: &'a [HttpMethod], handler: H) -> Self
+    where
+        H: Fn(&Request, &Data) -> Response + Send + Sync + 'static,
+    {
+        Self {
+            port,
+            pattern,
+            methods,
+            handler: Arc::new(handler),
+        }
+    }
 }
 
+/// Address a listener binds to. An IPv6 listener accepts only IPv6 clients unless
+/// `dual_stack` is set, in which case IPv4 clients arrive as v4-mapped addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenAddr {
+    pub addr: SocketAddr,
+    pub dual_stack: bool,
+}
+
+impl ListenAddr {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            dual_stack: false,
+        }
+    }
+
+    /// `[::]:port`, accepting IPv6 and IPv4 clients on one socket.
+    pub fn dual_stack(port: u16) -> Self {
+        Self {
+            addr: SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+            dual_stack: true,
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(addr)
+    }
+}
+
+const DEFAULT_SERVER_NAME: &str = concat!("http-rust/", env!("CARGO_PKG_VERSION"));
+
 pub struct Router {
-    routes: HashMap<u16, Vec<Route>>,
+    routes: HashMap<u16, RouteTable>,
+    upgrade_routes: HashMap<u16, Vec<UpgradeRoute>>,
+    upgrades: HashMap<RawFd, Box<dyn UpgradedHandler>>,
     epfd: i32,
     conns: HashMap<RawFd, Conn>,
     events: Vec<epoll_event>,
-    listen_fd_to_port: HashMap<RawFd, u16>,
+    listeners: HashMap<RawFd, ListenAddr>,
     sessions: HashMap<String, Session>,
+    request_budget: Option<Duration>,
+    parse_options: ParseOptions,
+    max_conns_per_session: Option<usize>,
+    session_conns: HashMap<String, usize>,
+    capture_exchanges: bool,
+    in_flight: HashMap<RawFd, (Request, Response)>,
+    completed_exchange: Option<(Request, Response)>,
+    keep_alive: bool,
+    max_response_body: Option<usize>,
+    newline_normalization: Option<LineEnding>,
+    observer: Option<Box<dyn ConnLifecycleObserver>>,
+    gzip_min_size: Option<usize>,
+    gzip_filter: CompressionFilter,
+    allowed_hosts: Option<Vec<String>>,
+    server_name: Option<String>,
+    session_rng: Box<dyn RngCore>,
+    slow_event_threshold: Option<Duration>,
+    slow_event_hook: Option<SlowEventHook>,
+    /// Route whose handler ran during the event being serviced.
+    current_route: Option<String>,
+    /// Client address of the connection whose request is being dispatched.
+    current_peer: Option<SocketAddr>,
+    preprocessor: Option<Preprocessor>,
+    middleware: Vec<Middleware>,
+    fallbacks: HashMap<u16, Handler>,
+    /// Keyed by status code.
+    error_renderers: HashMap<u16, ErrorRenderer>,
+    /// Shared with every static route so the setting applies whenever it is changed.
+    directory_listing: Arc<AtomicBool>,
+    auto_etag: bool,
+    accept_batch: Option<usize>,
+    /// Accepted sockets waiting for epoll registration while `accept_batch` is set.
+    pending_accepts: VecDeque<(RawFd, u16, Option<SocketAddr>)>,
+    /// `signalfd` for SIGTERM/SIGINT while `run` is serving.
+    signal_fd: Option<RawFd>,
+    shutdown_requested: bool,
+    header_timeout: Duration,
+    max_conns_per_ip: Option<usize>,
+    ip_conns: HashMap<IpAddr, usize>,
+    max_total_conns: Option<usize>,
+    /// Listeners are out of epoll because `max_total_conns` was reached.
+    accepting_paused: bool,
+    /// `recv` buffers of `config.read_buf_size` bytes.
+    read_buffers: BufferPool,
+    access_log: Option<AccessLogWriter>,
+    /// Access log lines not yet written; see `flush_access_log`.
+    access_log_pending: String,
+    metrics: Arc<Counters>,
+    cors: Option<CorsConfig>,
+    match_options: MatchOptions,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    config: RouterConfig,
+}
+
+/// Settings a router needs when it is built, for `Router::with_config`. Everything
+/// else is changed on a built router through its `set_*` methods.
+#[derive(Debug, Clone, Copy)]
+pub struct RouterConfig {
+    /// Applied to every listener, including the ones `run_workers` opens.
+    pub socket: SocketConfig,
+    /// Most events taken from one `epoll_wait`; more ready fds wait for the next turn
+    /// of the loop. Defaults to 128; 0 is treated as 1.
+    pub max_events: usize,
+    /// Bytes read from a client per `recv` call. Defaults to 4 KiB.
+    pub read_buf_size: usize,
+    /// Longest a loop iteration blocks waiting for events. Timeouts are only checked
+    /// between waits, so this is also their resolution. Defaults to 1 second.
+    pub epoll_wait: Duration,
+    /// Connections without any activity for this long are closed, after a `408` if
+    /// they stalled partway through a request. Defaults to 10 seconds.
+    pub idle_timeout: Duration,
+    /// Sessions unused for this long are forgotten. Defaults to 30 minutes.
+    pub session_ttl: Duration,
+    /// Registers client connections with `EPOLLET`. Off by default.
+    ///
+    /// The read and write paths run until `EAGAIN` and re-arm the fd (which rechecks
+    /// readiness) whenever a connection changes state, so edge-triggered mode is safe
+    /// here. Since the loop drains a fd either way, level-triggered mode rarely reports
+    /// one twice and the saving in wakeups is small; the cost is that a path stopping
+    /// short of `EAGAIN` without re-arming stalls its connection until the idle timeout
+    /// instead of being retried. Listeners and the signal fd stay level-triggered.
+    pub edge_triggered: bool,
+    /// Adds a `GET /metrics` route on this port serving `Router::metrics` in the
+    /// Prometheus text format. It only registers the route: the port still has to be
+    /// one the router listens on. A scrape sees itself as an open connection, but its
+    /// own response is only counted in the next scrape. Off by default.
+    pub metrics_port: Option<u16>,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            socket: SocketConfig::default(),
+            max_events: 128,
+            read_buf_size: 4096,
+            epoll_wait: Duration::from_secs(1),
+            idle_timeout: Duration::from_secs(10),
+            session_ttl: Duration::from_secs(60 * 30),
+            edge_triggered: false,
+            metrics_port: None,
+        }
+    }
+}
+
+impl RouterConfig {
+    /// Epoll interest for a client fd, edge-triggered when `edge_triggered` is set.
+    fn client_mask(&self, events: i32) -> u32 {
+        if self.edge_triggered {
+            (events | EPOLLET) as u32
+        } else {
+            events as u32
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -57,11 +403,16 @@ pub struct Session {
     pub created_at: Instant,
     pub last_seen: Instant,
     pub visits: u64,
+    /// Application data, e.g. a logged-in user id. Handlers read it through `Data`
+    /// and change it with `Data::set_session_value`.
+    pub data: HashMap<String, String>,
 }
 
 pub struct PendingRequest {
     pub header_bytes: Vec<u8>,
     pub body_bytes: Vec<u8>,
+    /// Trailer fields that followed a chunked body.
+    pub trailers: HeaderMap,
     pub local_port: u16,
 }
 
@@ -72,8 +423,32 @@ pub enum ReadOutcome {
 }
 
 impl Router {
-    // TODO: change to addresses instead of ports (NO NEED)
+    /// Listens on `0.0.0.0` (IPv4 only) for each of `ports`; see `new_on_addrs` for
+    /// other addresses and IPv6.
     pub fn new_on_ports(ports: &[u16]) -> Self {
+        let addrs: Vec<ListenAddr> = ports
+            .iter()
+            .map(|&port| ListenAddr::new(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))))
+            .collect();
+        Self::new_on_addrs(&addrs)
+    }
+
+    /// Opens one listener per entry of `addrs`. An IPv4 and an IPv6 listener may share
+    /// a port as long as the IPv6 one is not dual-stack. Routes are still registered
+    /// per port, so every address with that port serves the same routes. Addresses that
+    /// fail to bind are logged and skipped.
+    pub fn new_on_addrs(addrs: &[ListenAddr]) -> Self {
+        Self::with_config(addrs, RouterConfig::default())
+    }
+
+    /// Like `new_on_addrs`, with settings that have to be known before the listeners
+    /// are opened.
+    pub fn with_config(addrs: &[ListenAddr], config: RouterConfig) -> Self {
+        Self::with_listeners(addrs, false, config)
+    }
+
+    /// Like `with_config`, optionally setting `SO_REUSEPORT` on the listeners.
+    fn with_listeners(addrs: &[ListenAddr], reuse_port: bool, config: RouterConfig) -> Self {
         let epfd = match create_epoll() {
             Ok(fd) => fd,
             Err(err) => {
@@ -81,106 +456,552 @@ impl Router {
                 -1
             }
         };
-        let mut listen_fd_to_port: HashMap<RawFd, u16> = HashMap::new();
+        let mut listeners: HashMap<RawFd, ListenAddr> = HashMap::new();
 
-        for &port in ports {
-            // TODO: pass the address here (NO NEED)
-            match create_listen_socket(port) {
+        for &listen in addrs {
+            let addr = listen.addr;
+            match create_listen_socket(&addr, reuse_port, !listen.dual_stack, &config.socket) {
                 Ok(listen_fd) => {
-                    info!("listening on 0.0.0.0:{port}");
+                    info!("listening", "addr" => addr);
                     if let Err(err) = epoll_add(epfd, listen_fd, EPOLLIN as u32) {
-                        eprintln!("could not register listener on port {port} in epoll: {err}");
+                        eprintln!("could not register listener on {addr} in epoll: {err}");
                         close_fd(listen_fd);
                         continue;
                     }
-                    listen_fd_to_port.insert(listen_fd, port);
+                    listeners.insert(listen_fd, listen);
                 }
                 Err(err) => {
-                    println!("could not create a listener on port: {port}, error: {err}");
+                    println!("could not create a listener on: {addr}, error: {err}");
                 }
             };
         }
 
         let conns: HashMap<RawFd, Conn> = HashMap::new();
-        let events: Vec<epoll_event> = vec![unsafe { mem::zeroed() }; 128];
+        let events: Vec<epoll_event> = vec![unsafe { mem::zeroed() }; config.max_events.max(1)];
 
-        Self {
+        let mut router = Self {
             routes: HashMap::new(),
+            upgrade_routes: HashMap::new(),
+            upgrades: HashMap::new(),
             epfd,
             conns,
             events,
-            listen_fd_to_port,
+            listeners,
             sessions: HashMap::new(),
+            request_budget: None,
+            parse_options: ParseOptions::default(),
+            max_conns_per_session: None,
+            session_conns: HashMap::new(),
+            capture_exchanges: false,
+            in_flight: HashMap::new(),
+            completed_exchange: None,
+            keep_alive: true,
+            max_response_body: None,
+            newline_normalization: None,
+            observer: None,
+            gzip_min_size: None,
+            gzip_filter: CompressionFilter::default(),
+            allowed_hosts: None,
+            server_name: Some(DEFAULT_SERVER_NAME.to_string()),
+            session_rng: Box::new(OsRng),
+            slow_event_threshold: None,
+            slow_event_hook: None,
+            current_route: None,
+            current_peer: None,
+            preprocessor: None,
+            middleware: Vec::new(),
+            fallbacks: HashMap::new(),
+            error_renderers: HashMap::new(),
+            directory_listing: Arc::new(AtomicBool::new(false)),
+            auto_etag: false,
+            accept_batch: None,
+            pending_accepts: VecDeque::new(),
+            signal_fd: None,
+            shutdown_requested: false,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            max_conns_per_ip: None,
+            ip_conns: HashMap::new(),
+            max_total_conns: None,
+            accepting_paused: false,
+            read_buffers: BufferPool::new(config.read_buf_size),
+            access_log: Some(Arc::new(Mutex::new(PrintStdout))),
+            access_log_pending: String::new(),
+            metrics: Arc::default(),
+            cors: None,
+            match_options: MatchOptions::default(),
+            state: None,
+            config,
+        };
+        if let Some(port) = config.metrics_port {
+            router.add_metrics_route(port);
         }
+        router
+    }
+
+    /// Opens one listener per port in `ports`. Ports that fail to bind are logged and
+    /// skipped; check `bound_ports` to see which ones succeeded.
+    pub fn new_on_port_range(ports: RangeInclusive<u16>) -> Self {
+        let ports: Vec<u16> = ports.collect();
+        let router = Self::new_on_ports(&ports);
+
+        let bound = router.bound_ports();
+        info!(
+            "bound port range",
+            "requested" => ports.len(),
+            "bound" => bound.len()
+        );
+        router
+    }
+
+    /// Ports that currently have a listening socket, in ascending order.
+    pub fn bound_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.listeners.values().map(|l| l.addr.port()).collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    }
+
+    /// Addresses that currently have a listening socket, in ascending order.
+    pub fn bound_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs: Vec<SocketAddr> = self.listeners.values().map(|l| l.addr).collect();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    /// Gives every request a deadline `budget` after it was parsed, exposed through
+    /// `Data::deadline`.
+    pub fn set_request_budget(&mut self, budget: Duration) -> &mut Self {
+        self.request_budget = Some(budget);
+        self
+    }
+
+    /// Enables or disables reading unframed HTTP/1.0 POST bodies until the client
+    /// closes its side of the connection (off by default). Only requests that send
+    /// `Connection: close` are read this way; without it the request has no body.
+    pub fn set_read_body_until_close(&mut self, enabled: bool) -> &mut Self {
+        self.parse_options.read_body_until_close = enabled;
+        self
+    }
+
+    /// Answers requests whose body is larger than `max` bytes with 413 instead of
+    /// buffering them. Unlimited by default.
+    pub fn set_max_body_bytes(&mut self, max: usize) -> &mut Self {
+        self.parse_options.max_body_bytes = Some(max);
+        self
+    }
+
+    /// Answers requests whose head (request line plus headers) is larger than `max`
+    /// bytes with 431, as soon as that many bytes arrive without the blank line that
+    /// ends it. Defaults to 64 KiB.
+    pub fn set_max_header_bytes(&mut self, max: usize) -> &mut Self {
+        self.parse_options.max_header_bytes = Some(max);
+        self
+    }
+
+    /// Rejects requests whose head, chunk size lines or trailers end a line with a bare
+    /// LF instead of CRLF (on by default). Turning it off only stops the rejection: a
+    /// bare LF still does not end a line, so such requests rarely parse as intended.
+    pub fn set_strict_line_endings(&mut self, enabled: bool) -> &mut Self {
+        self.parse_options.strict_line_endings = enabled;
+        self
+    }
+
+    /// Answers requests with more than `max` header lines with 431. Defaults to 100.
+    pub fn set_max_header_count(&mut self, max: usize) -> &mut Self {
+        self.parse_options.max_header_count = Some(max);
+        self
+    }
+
+    /// Answers with 408 and closes when a request head takes longer than `timeout` to
+    /// arrive, however steadily its bytes trickle in (slowloris). The clock starts at
+    /// accept, and on a kept-alive connection at the first byte of the next request.
+    /// Defaults to 5 seconds.
+    pub fn set_header_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Allows or forbids persistent connections. When disabled every response carries
+    /// `Connection: close`, whatever the client asked for.
+    pub fn set_keep_alive(&mut self, enabled: bool) -> &mut Self {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// Caps response bodies at `max` bytes. Larger handler bodies are replaced with a
+    /// 500; streamed bodies are cut off and the connection closed. Unlimited by default.
+    pub fn set_max_response_body(&mut self, max: usize) -> &mut Self {
+        self.max_response_body = Some(max);
+        self
+    }
+
+    /// Registers at most `batch` newly accepted connections per event-loop iteration,
+    /// queueing the rest for later iterations. Off by default, which registers a whole
+    /// accept burst at once.
+    ///
+    /// Without a batch a burst of thousands of connects stalls the loop while they are
+    /// all added to epoll, delaying every established connection. With one, existing
+    /// connections keep being serviced between batches, at the cost of the newest
+    /// clients waiting a few extra iterations before their first read. Smaller batches
+    /// smooth latency more and admit a burst more slowly. `batch` must be at least 1.
+    pub fn set_accept_batch(&mut self, batch: usize) -> &mut Self {
+        assert!(batch > 0, "accept batch must be at least 1");
+        self.accept_batch = Some(batch);
+        self
+    }
+
+    /// Rewrites line endings of textual handler bodies to `ending` (for legacy clients
+    /// that insist on CRLF, or LF). Off by default.
+    pub fn set_newline_normalization(&mut self, ending: LineEnding) -> &mut Self {
+        self.newline_normalization = Some(ending);
+        self
+    }
+
+    /// Makes `state` available to every handler, middleware and upgrade route through
+    /// `Data::state::<T>()`. It is shared, not copied: with `run_workers` all workers
+    /// see the same value from their own threads, so anything mutable inside it needs
+    /// atomics or a lock.
+    pub fn set_state<T: Any + Send + Sync>(&mut self, state: T) -> &mut Self {
+        self.state = Some(Arc::new(state));
+        self
+    }
+
+    /// Tags `200 OK` answers to GET and HEAD with an `ETag` hashed from the body (unless
+    /// the handler set one) and answers a matching `If-None-Match` with 304. Off by
+    /// default because every such body gets hashed.
+    pub fn set_auto_etag(&mut self, enabled: bool) -> &mut Self {
+        self.auto_etag = enabled;
+        self
+    }
+
+    /// Restricts gzip to response content types accepted by `filter`. The default
+    /// skips already-compressed formats such as PNG, JPEG and zip.
+    pub fn set_gzip_content_types(&mut self, filter: CompressionFilter) -> &mut Self {
+        self.gzip_filter = filter;
+        self
+    }
+
+    /// Only answers requests whose `Host` (without port, case-insensitive) is one of
+    /// `hosts`; others get a 400. Guards against DNS rebinding. HTTP/1.0 requests
+    /// without a `Host` are still let through.
+    pub fn set_allowed_hosts<S: AsRef<str>>(&mut self, hosts: &[S]) -> &mut Self {
+        let hosts = hosts
+            .iter()
+            .map(|h| h.as_ref().to_ascii_lowercase())
+            .collect();
+        self.allowed_hosts = Some(hosts);
+        self
+    }
+
+    fn host_allowed(&self, req: &Request) -> bool {
+        let (Some(allowed), Some(host)) = (&self.allowed_hosts, req.headers.get("host")) else {
+            return true;
+        };
+        let name = match host.rsplit_once(':') {
+            // Keep bracketed IPv6 literals intact: `[::1]:8080` -> `[::1]`.
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        allowed.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Value of the `Server` header added to responses that lack one; `None` omits it.
+    /// Defaults to `http-rust/<crate version>`.
+    pub fn set_server_name(&mut self, name: Option<&str>) -> &mut Self {
+        self.server_name = name.map(str::to_string);
+        self
+    }
+
+    /// Gzips handler bodies of at least `min_size` bytes for clients whose
+    /// `Accept-Encoding` allows it. Off by default.
+    pub fn set_gzip(&mut self, min_size: usize) -> &mut Self {
+        self.gzip_min_size = Some(min_size);
+        self
+    }
+
+    /// Reports connection lifecycle transitions to `observer`.
+    pub fn set_lifecycle_observer(
+        &mut self,
+        observer: impl ConnLifecycleObserver + 'static,
+    ) -> &mut Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Chooses how absolute-form targets (`GET http://host/x`) are treated; they are
+    /// rejected by default since this server is not a proxy.
+    pub fn set_absolute_form(&mut self, policy: AbsoluteFormPolicy) -> &mut Self {
+        self.parse_options.absolute_form = policy;
+        self
+    }
+
+    /// Chooses whether `/users/` is the same route as `/users`, a different one, or a
+    /// redirect to it. They are merged by default.
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) -> &mut Self {
+        self.match_options.trailing_slash = policy;
+        self
+    }
+
+    /// Matches the literal segments of route patterns ignoring ASCII case, so `/API/v1`
+    /// reaches a `/api/v1` route. Values captured by `:name` and `*name` segments keep
+    /// the case the client sent. Off by default.
+    pub fn set_case_insensitive_paths(&mut self, enabled: bool) -> &mut Self {
+        self.match_options.ignore_case = enabled;
+        self
     }
 
+    /// Registers `handler` for `methods` on `pattern`. `handler` is any closure or
+    /// function of the `Handler` shape; captured state is shared by every request.
     pub fn add_route<H>(&mut self, port: u16, pattern: &str, methods: Vec<HttpMethod>, handler: H)
     where
         H: Fn(&Request, &Data) -> Response + Send + Sync + 'static,
     {
+        self.push_route(port, pattern, methods, Arc::new(handler));
+    }
+
+    /// Registers every route in `specs`, in order, as if `add_route` had been called
+    /// for each one.
+    pub fn add_routes(&mut self, specs: &[RouteSpec]) {
+        for spec in specs {
+            self.push_route(
+                spec.port,
+                spec.pattern,
+                spec.methods.to_vec(),
+                spec.handler.clone(),
+            );
+        }
+    }
+
+    /// Panics on patterns `check_pattern` rejects, since those are programming errors.
+    /// Returns the route's index in its port's table.
+    fn push_route(
+        &mut self,
+        port: u16,
+        pattern: &str,
+        methods: Vec<HttpMethod>,
+        handler: Handler,
+    ) -> usize {
+        route_matching::assert_valid_pattern(pattern);
         self.routes.entry(port).or_default().push(Route {
             methods,
             pattern: pattern.to_string(),
-            handler: Arc::new(handler),
-        });
+            handler,
+            middleware: Vec::new(),
+        })
     }
 
-    pub fn handle(&mut self, local_port: u16, req: &Request) -> Response {
-        let match_result = {
-            let Some(routes) = self.routes.get(&local_port) else {
-                return error_response(&req.version, StatusCode::NotFound);
-            };
+    /// Serves in-memory bytes (e.g. from `include_bytes!`) at `path` without touching disk.
+    pub fn add_static_asset(
+        &mut self,
+        port: u16,
+        path: &str,
+        bytes: impl Into<Arc<[u8]>>,
+        content_type: &str,
+    ) {
+        self.add_route(
+            port,
+            path,
+            vec![HttpMethod::Get],
+            static_asset_handler(bytes.into(), content_type),
+        );
+    }
 
-            let mut matched_path_but_wrong_method = false;
-            let mut found: Option<(Handler, HashMap<String, String>)> = None;
+    /// Installs a check that runs first for every request on every port, e.g. to
+    /// block unwanted clients. A `Some` response is sent as is.
+    pub fn set_global_preprocessor<P>(&mut self, preprocessor: P) -> &mut Self
+    where
+        P: Fn(&Request, &Data) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.preprocessor = Some(Arc::new(preprocessor));
+        self
+    }
 
-            for route in routes {
-                let Some(path_value) = route_matching::match_pattern(&route.pattern, &req.path)
-                else {
-                    continue;
-                };
+    /// Lets static routes answer directories without an `index.html` with a listing
+    /// of their contents instead of 403. Off by default since it exposes file names.
+    pub fn set_directory_listing(&mut self, enabled: bool) -> &mut Self {
+        self.directory_listing.store(enabled, Ordering::Relaxed);
+        self
+    }
 
-                if !route.methods.iter().any(|m| *m == req.method) {
-                    matched_path_but_wrong_method = true;
-                    continue;
-                }
+    /// Serves the files under `fs_root` at `url_prefix`, so `/static/css/site.css`
+    /// maps to `<fs_root>/css/site.css`. Only GET (and HEAD) are routed. Directories
+    /// are served through their `index.html`; see `set_directory_listing` otherwise.
+    pub fn add_static(&mut self, port: u16, url_prefix: &str, fs_root: impl Into<PathBuf>) {
+        let pattern = format!("{}/*path", url_prefix.trim_end_matches('/'));
+        self.add_route(
+            port,
+            &pattern,
+            vec![HttpMethod::Get],
+            static_dir_handler(fs_root, Arc::clone(&self.directory_listing)),
+        );
+    }
 
-                found = Some((route.handler.clone(), path_value));
-                break;
-            }
+    /// Answers `req` as if it arrived on `local_port`. The response carries the
+    /// request's id in `X-Request-Id` unless the handler set that header itself, and
+    /// the CORS headers when `set_cors` allows the request's origin.
+    pub fn handle(&mut self, local_port: u16, req: &Request) -> Response {
+        let request_id = request_id::request_id_for(req, &mut OsRng);
+        let mut resp = match self.cors_preflight(local_port, req) {
+            Some(preflight) => preflight,
+            None => self.route_request(local_port, req, request_id.clone()),
+        };
+        self.apply_cors(req, &mut resp);
+        if resp.raw.is_none() && resp.headers.get("x-request-id").is_none() {
+            resp.headers.insert("X-Request-Id", &request_id);
+        }
+        resp
+    }
 
-            (found, matched_path_but_wrong_method)
+    fn route_request(&mut self, local_port: u16, req: &Request, request_id: String) -> Response {
+        let deadline = self.request_budget.map(|budget| Instant::now() + budget);
+        let mut data = Data {
+            request_id: Some(request_id),
+            body: req.data.body.clone(),
+            deadline,
+            accept: req
+                .headers
+                .get("accept")
+                .map(parse_accept)
+                .unwrap_or_default(),
+            content_type: req.headers.get("content-type").and_then(ContentType::parse),
+            peer_addr: self.current_peer,
+            state: self.state.clone(),
+            ..Data::with_query(&req.query)
         };
+        if let Some(preprocessor) = &self.preprocessor
+            && let Some(resp) = preprocessor(req, &data)
+        {
+            return resp;
+        }
+
+        let empty = RouteTable::default();
+        let table = self.routes.get(&local_port).unwrap_or(&empty);
+        if self.match_options.trailing_slash == TrailingSlash::RedirectToNoSlash
+            && let Some(canonical) = table.slash_redirect(&req.path, self.match_options)
+        {
+            let location = if req.query.is_empty() {
+                canonical.to_string()
+            } else {
+                format!("{canonical}?{}", req.query)
+            };
+            return redirect(&req.version, StatusCode::MovedPermanently, &location);
+        }
 
-        let (found, matched_path_but_wrong_method) = match_result;
-        let Some((handler, path_value)) = found else {
-            if matched_path_but_wrong_method {
-                return error_response(&req.version, StatusCode::MethodNotAllowed);
+        // HEAD falls back to the GET handler; the body is dropped on the way out.
+        let accepts = |route: &Route| {
+            route.methods.contains(&req.method)
+                || (matches!(req.method, HttpMethod::Head)
+                    && route.methods.contains(&HttpMethod::Get))
+        };
+        let Some(route) = table
+            .first_match(&req.path, self.match_options, accepts)
+            .cloned()
+        else {
+            // Methods accepted by every route whose pattern matched, for the 405 `Allow`.
+            let mut allowed: Vec<HttpMethod> = Vec::new();
+            for route in table.all_matches(&req.path, self.match_options) {
+                for method in &route.methods {
+                    if !allowed.contains(method) {
+                        allowed.push(method.clone());
+                    }
+                }
+            }
+            if allowed.is_empty() {
+                return match self.fallbacks.get(&local_port) {
+                    Some(fallback) => fallback(req, &data),
+                    None => self.render_error(Some(req), StatusCode::NotFound),
+                };
+            }
+            if allowed.contains(&HttpMethod::Get) && !allowed.contains(&HttpMethod::Head) {
+                allowed.push(HttpMethod::Head);
             }
-            return error_response(&req.version, StatusCode::NotFound);
+            let allow: Vec<&str> = allowed.iter().map(HttpMethod::as_str).collect();
+            let mut resp = self.render_error(Some(req), StatusCode::MethodNotAllowed);
+            resp.headers.insert("Allow", &allow.join(", "));
+            return resp;
         };
+        // Only the chosen route's parameters are extracted.
+        let path_value =
+            route_matching::match_pattern(&route.pattern, &req.path, self.match_options)
+                .unwrap_or_default();
 
+        let chain: Vec<Middleware> = self
+            .middleware
+            .iter()
+            .chain(&route.middleware)
+            .cloned()
+            .collect();
+        self.current_route = Some(route.pattern);
         let now = Instant::now();
-        let (session_id, is_new_session) = session::resolve_session(&mut self.sessions, req, now);
+        let (session_id, is_new_session) =
+            session::resolve_session(&mut self.sessions, self.session_rng.as_mut(), req, now);
 
-        let data = Data {
-            path_value,
-            query_value: route_matching::parse_query(&req.query),
-            session_id: session_id.clone(),
-            is_new_session,
-            body: req.data.body.clone(),
-        };
+        data.path_value = path_value;
+        data.session_id = session_id.clone();
+        data.is_new_session = is_new_session;
+        if let Some(session) = session_id.as_ref().and_then(|sid| self.sessions.get(sid)) {
+            data.session_data = session.data.clone();
+        }
 
-        let mut resp = handler(req, &data);
+        let mut resp = Next::new(&chain, &route.handler).run(req, &data);
+        if let Some(session) = session_id
+            .as_ref()
+            .and_then(|sid| self.sessions.get_mut(sid))
+        {
+            session::apply_session_writes(session, data.session_writes.take());
+        }
+        if let Some(ending) = self.newline_normalization {
+            resp.normalize_newlines(ending);
+        }
+        // A partial body must not be re-encoded, so ranged responses skip gzip.
+        let ranged = matches!(req.method, HttpMethod::Get | HttpMethod::Head)
+            && req
+                .headers
+                .get("range")
+                .is_some_and(|range| resp.apply_range(range));
+        if !ranged
+            && let Some(min_size) = self.gzip_min_size
+            && resp.body.len() >= min_size
+            && req.headers.get("accept-encoding").is_some_and(accepts_gzip)
+            && self.gzip_filter.allows(
+                resp.headers
+                    .get("content-type")
+                    .and_then(ContentType::parse)
+                    .as_ref(),
+            )
+        {
+            resp.compress_gzip();
+        }
+        if self.auto_etag {
+            resp = apply_etag(req, resp);
+        }
 
         if is_new_session && let Some(sid) = session_id {
             let cookie = format!("sid={sid}; Path=/; HttpOnly; SameSite=Lax");
-            resp.headers.insert("Set-Cookie", &cookie);
+            resp.headers.append("Set-Cookie", &cookie);
         }
 
         resp
     }
 
+    /// Runs the normal event loop until one request has been handled and its response
+    /// fully flushed, then returns that request/response pair. Meant for deterministic
+    /// tests that drive the server without spawning threads.
+    pub fn serve_one(&mut self) -> io::Result<(Request, Response)> {
+        self.capture_exchanges = true;
+        let result = loop {
+            if let Some(exchange) = self.completed_exchange.take() {
+                break Ok(exchange);
+            }
+            if let Err(err) = self.handle_connections() {
+                break Err(err);
+            }
+        };
+        self.capture_exchanges = false;
+        self.in_flight.clear();
+        result
+    }
+
     pub fn listen_and_serve(&mut self) {
         loop {
             if let Err(err) = self.handle_connections() {
@@ -190,3 +1011,42 @@ impl Router {
         }
     }
 }
+
+/// Adds an `ETag` to a complete in-memory `200 OK` and swaps it for a 304 when the
+/// request's `If-None-Match` already names it.
+fn apply_etag(req: &Request, mut resp: Response) -> Response {
+    if !matches!(req.method, HttpMethod::Get | HttpMethod::Head)
+        || resp.status.code() != 200
+        || resp.stream.is_some()
+        || resp.raw.is_some()
+        || resp.file.is_some()
+    {
+        return resp;
+    }
+
+    let etag = match resp.headers.get("etag") {
+        Some(etag) => etag.to_string(),
+        None => {
+            let etag = etag_for(&resp.body);
+            resp.headers.insert("ETag", &etag);
+            etag
+        }
+    };
+    if req
+        .headers
+        .get("if-none-match")
+        .is_some_and(|v| if_none_match_matches(v, &etag))
+    {
+        let mut not_modified = not_modified(&resp.version, &etag);
+        for name in ["cache-control", "vary", "content-location", "expires"] {
+            if let Some(value) = resp.headers.get(name) {
+                not_modified.headers.insert(name, value);
+            }
+        }
+        return not_modified;
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::https::{Response, StatusCode, response_with_body};
+
+use super::Data;
+
+/// Why a path or query parameter could not be turned into the type a handler asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// The route has no such capture, or the query string lacks the key.
+    Missing(String),
+    /// The parameter is present but does not parse as the requested type.
+    Invalid { name: String, value: String },
+}
+
+impl ParamError {
+    /// A `400 Bad Request` naming the parameter, for handlers that just give up.
+    pub fn to_response(&self, version: &str) -> Response {
+        response_with_body(
+            version,
+            StatusCode::BadRequest,
+            "text/plain; charset=utf-8",
+            format!("{self}\n").into_bytes(),
+        )
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "missing parameter `{name}`"),
+            Self::Invalid { name, value } => {
+                write!(f, "invalid value {value:?} for parameter `{name}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+fn parse_param<T: FromStr>(name: &str, value: Option<&String>) -> Result<T, ParamError> {
+    let value = value.ok_or_else(|| ParamError::Missing(name.to_string()))?;
+    value.parse().map_err(|_| ParamError::Invalid {
+        name: name.to_string(),
+        value: value.clone(),
+    })
+}
+
+impl Data {
+    /// Path capture `name` parsed as a `T`, e.g. `data.path_parse::<u32>("id")` for
+    /// `/users/:id`.
+    pub fn path_parse<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        parse_param(name, self.path_value.get(name))
+    }
+
+    /// Path capture `name` as a signed integer.
+    pub fn path_int(&self, name: &str) -> Result<i64, ParamError> {
+        self.path_parse(name)
+    }
+
+    /// First value of query parameter `name` parsed as a `T`.
+    pub fn query_parse<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        parse_param(name, self.query_value.get(name))
+    }
+
+    /// First value of query parameter `name` as a signed integer.
+    pub fn query_int(&self, name: &str) -> Result<i64, ParamError> {
+        self.query_parse(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_path_and_query_values() {
+        let mut data = Data::with_query("page=3&sort=name&limit=ten");
+        data.path_value.insert("id".to_string(), "42".to_string());
+
+        assert_eq!(data.path_int("id"), Ok(42));
+        assert_eq!(data.path_parse::<u8>("id"), Ok(42));
+        assert_eq!(data.query_int("page"), Ok(3));
+        assert_eq!(data.query_parse::<String>("sort").as_deref(), Ok("name"));
+
+        assert_eq!(
+            data.path_int("slug"),
+            Err(ParamError::Missing("slug".to_string()))
+        );
+        assert_eq!(
+            data.query_int("limit"),
+            Err(ParamError::Invalid {
+                name: "limit".to_string(),
+                value: "ten".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn errors_become_bad_requests() {
+        let err = Data::default().path_int("id").unwrap_err();
+        let resp = err.to_response("HTTP/1.1");
+        assert_eq!(resp.status.code(), 400);
+        assert_eq!(&resp.body[..], b"missing parameter `id`\n");
+    }
+}
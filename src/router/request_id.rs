@@ -0,0 +1,53 @@
+use rand::RngCore;
+
+use crate::https::Request;
+
+/// Longest incoming `X-Request-Id` that is reused rather than replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The client's `X-Request-Id` when it is well-formed, otherwise a fresh random one.
+pub(super) fn request_id_for(req: &Request, rng: &mut dyn RngCore) -> String {
+    match req.headers.get("x-request-id") {
+        Some(id) if is_well_formed(id) => id.to_string(),
+        _ => generate_request_id(rng),
+    }
+}
+
+/// 1 to 128 characters from a set that needs no quoting in headers or log lines.
+fn is_well_formed(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=".contains(&b))
+}
+
+fn generate_request_id(rng: &mut dyn RngCore) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conn::ParseOptions;
+    use crate::router::request_parsing::parse_request;
+
+    fn with_id(id: &str) -> Request {
+        let head = format!("GET / HTTP/1.1\r\nHost: x\r\nX-Request-Id: {id}\r\n\r\n");
+        parse_request(head.as_bytes(), b"", &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn reuses_well_formed_ids_and_replaces_the_rest() {
+        let mut rng = rand::rngs::mock::StepRng::new(1, 1);
+        assert_eq!(request_id_for(&with_id("abc-123"), &mut rng), "abc-123");
+
+        for bad in ["has space", "quote\"d", &"x".repeat(129)] {
+            let id = request_id_for(&with_id(bad), &mut rng);
+            assert_eq!(id.len(), 32, "{bad:?}");
+            assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        }
+    }
+}
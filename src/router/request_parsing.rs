@@ -1,35 +1,21 @@
-use std::collections::HashMap;
-
-use crate::https::{HttpMethod, Request, StatusCode};
+use crate::conn::{AbsoluteFormPolicy, ParseOptions, RequestHead, parse_head};
+use crate::https::{HeaderMap, HttpMethod, Request, StatusCode};
 
 use super::Data;
+use super::route_matching::percent_decode;
 
 pub(super) fn parse_request(
     header_bytes: &[u8],
     body: &[u8],
+    options: &ParseOptions,
 ) -> Result<Request, (StatusCode, String)> {
     let bad_request = |reason: &str| (StatusCode::BadRequest, reason.to_string());
-    let text = std::str::from_utf8(header_bytes)
-        .map_err(|_| bad_request("request headers are not valid UTF-8"))?;
-    let mut lines = text.split("\r\n");
-
-    let request_line = lines
-        .next()
-        .ok_or_else(|| bad_request("missing request line"))?;
-    let mut parts = request_line.split_whitespace();
-    let method = parts
-        .next()
-        .ok_or_else(|| bad_request("missing HTTP method"))?;
-    let raw_path = parts
-        .next()
-        .ok_or_else(|| bad_request("missing request path"))?;
-    let version = parts
-        .next()
-        .ok_or_else(|| bad_request("missing HTTP version"))?;
-
-    if parts.next().is_some() {
-        return Err(bad_request("request line has extra fields"));
-    }
+    let RequestHead {
+        method,
+        target: raw_path,
+        version,
+        mut headers,
+    } = parse_head(header_bytes, options)?;
 
     if version != "HTTP/1.1" && version != "HTTP/1.0" {
         return Err((
@@ -39,25 +25,65 @@ pub(super) fn parse_request(
     }
 
     let method = HttpMethod::from_str(method);
-    if matches!(method, HttpMethod::Post) && body.is_empty() {
-        return Err(bad_request("POST request requires a non-empty body"));
+    if matches!(
+        method,
+        HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch
+    ) && body.is_empty()
+    {
+        return Err(bad_request(
+            "POST/PUT/PATCH request requires a non-empty body",
+        ));
+    }
+    if matches!(method, HttpMethod::Head) && !body.is_empty() {
+        return Err(bad_request("HEAD request must not carry a body"));
     }
 
-    let mut headers = crate::https::HeaderMap::default();
-    for line in lines {
-        if line.is_empty() {
-            break;
-        }
+    let host_count = headers.get_all("host").count();
+    // RFC 9112 3.2: HTTP/1.1 requests carry exactly one Host; 1.0 ones may omit it.
+    if host_count > 1 || (host_count == 0 && version == "HTTP/1.1") {
+        return Err(bad_request("request must carry exactly one Host header"));
+    }
 
-        if let Some((name, value)) = line.split_once(':') {
-            headers.insert(name, value);
-        }
+    if method.honors_max_forwards()
+        && let Some(value) = headers.get("max-forwards")
+        && value.parse::<u32>().is_err()
+    {
+        return Err(bad_request("Max-Forwards must be a non-negative integer"));
     }
 
+    let raw_path = match split_absolute_form(raw_path) {
+        None => raw_path,
+        Some((scheme, _, _)) if scheme != "http" && scheme != "https" => {
+            return Err(bad_request(
+                "absolute-form target has an unsupported scheme",
+            ));
+        }
+        Some(_) if options.absolute_form == AbsoluteFormPolicy::Reject => {
+            return Err(bad_request("absolute-form targets are not accepted"));
+        }
+        Some((_, authority, path)) => {
+            if authority.is_empty() {
+                return Err(bad_request("absolute-form target has no authority"));
+            }
+            // RFC 9112 3.2.2: the target's authority overrides any Host header.
+            headers.insert("Host", authority);
+            path
+        }
+    };
+
     let (path, query) = raw_path
         .split_once('?')
         .map(|(p, q)| (p.to_string(), q.to_string()))
         .unwrap_or((raw_path.to_string(), String::new()));
+    if path.split('/').any(|seg| percent_decode(seg).is_none()) {
+        return Err(bad_request("request path has an invalid percent-encoding"));
+    }
+    // `http://host` and `http://host?x` have an empty path, which means `/`.
+    let path = if path.is_empty() {
+        "/".to_string()
+    } else {
+        path
+    };
 
     Ok(Request {
         method,
@@ -65,12 +91,21 @@ pub(super) fn parse_request(
         query,
         version: version.to_string(),
         headers,
+        trailers: HeaderMap::default(),
         data: Data {
             body: body.to_vec(),
-            path_value: HashMap::new(),
-            query_value: HashMap::new(),
-            session_id: None,
-            is_new_session: false,
+            ..Default::default()
         },
     })
 }
+
+/// Splits `scheme://authority/path?query` into the lowercased scheme, the authority,
+/// and the remainder. Returns `None` for origin-form targets.
+fn split_absolute_form(target: &str) -> Option<(String, &str, &str)> {
+    if target.starts_with('/') {
+        return None;
+    }
+    let (scheme, rest) = target.split_once("://")?;
+    let (authority, remainder) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    Some((scheme.to_ascii_lowercase(), authority, remainder))
+}
@@ -1,36 +1,168 @@
 use std::collections::HashMap;
 
-pub(super) fn match_pattern(pattern: &str, req_path: &str) -> Option<HashMap<String, String>> {
-    let p = pattern.trim_matches('/');
-    let r = req_path.trim_matches('/');
-
-    let p_segs: Vec<&str> = if p.is_empty() {
-        vec![]
-    } else {
-        p.split('/').collect()
+use super::segment_regex;
+
+/// How request paths are compared with route patterns.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct MatchOptions {
+    pub(super) trailing_slash: TrailingSlash,
+    /// Compare literal segments ignoring ASCII case. Captured values keep the case
+    /// the client sent.
+    pub(super) ignore_case: bool,
+}
+
+/// How a trailing slash on the request path affects routing. The root path `/` never
+/// counts as having one, and routes ending in a `*name` segment match either form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `/users` and `/users/` both match the `/users` route.
+    #[default]
+    Merge,
+    /// `/users/` only matches a route registered as `/users/`, and `/users` only one
+    /// registered without the slash.
+    Strict,
+    /// Answer `/users/` with a 301 to `/users` when a route matches it, so each
+    /// resource has a single URL. The query string is kept. Upgrade requests cannot
+    /// follow a redirect, so upgrade routes match as under `Merge`.
+    RedirectToNoSlash,
+}
+
+/// A `:name` pattern segment. `:name(re)` only matches segments the constraint `re`
+/// matches in full (see `segment_regex::is_match`), and a last `:name?` segment may be
+/// left out of the path, in which case nothing is captured. Both can be combined as
+/// `:name(re)?`.
+pub(super) struct Param<'a> {
+    pub(super) name: &'a str,
+    pub(super) constraint: Option<&'a str>,
+    pub(super) optional: bool,
+}
+
+/// The parameter a pattern segment declares, if it is one.
+pub(super) fn parse_param(segment: &str) -> Option<Param<'_>> {
+    let spec = segment.strip_prefix(':')?;
+    let (spec, optional) = match spec.strip_suffix('?') {
+        Some(spec) => (spec, true),
+        None => (spec, false),
     };
-    let r_segs: Vec<&str> = if r.is_empty() {
-        vec![]
-    } else {
-        r.split('/').collect()
+    let (name, constraint) = match spec.split_once('(') {
+        Some((name, re)) => (name, Some(re.strip_suffix(')').unwrap_or(re))),
+        None => (spec, None),
     };
+    Some(Param {
+        name,
+        constraint,
+        optional,
+    })
+}
 
-    if p_segs.len() != r_segs.len() {
+/// Rejects patterns `match_pattern` cannot honour: a `*name` segment anywhere but
+/// last, a `:`/`*` segment without a name, an optional parameter anywhere but last,
+/// or a constraint `segment_regex` cannot interpret.
+pub(super) fn check_pattern(pattern: &str) -> Result<(), String> {
+    let segments: Vec<&str> = pattern
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        if *segment == "*" {
+            return Err(format!("segment {segment:?} has no name"));
+        }
+        if segment.starts_with('*') && !last {
+            return Err(format!("wildcard {segment:?} must be the last segment"));
+        }
+        let Some(param) = parse_param(segment) else {
+            continue;
+        };
+        if param.name.is_empty() {
+            return Err(format!("segment {segment:?} has no name"));
+        }
+        if param.optional && !last {
+            return Err(format!("optional {segment:?} must be the last segment"));
+        }
+        if let Some(re) = param.constraint {
+            if !segment.trim_end_matches('?').ends_with(')') {
+                return Err(format!("constraint of {segment:?} is not closed"));
+            }
+            segment_regex::check(re)
+                .map_err(|problem| format!("constraint of {segment:?}: {problem}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Registration-time guard shared by every `add_*route` method.
+pub(super) fn assert_valid_pattern(pattern: &str) {
+    if let Err(problem) = check_pattern(pattern) {
+        panic!("invalid route pattern {pattern:?}: {problem}");
+    }
+}
+
+pub(super) fn match_pattern(
+    pattern: &str,
+    req_path: &str,
+    options: MatchOptions,
+) -> Option<HashMap<String, String>> {
+    if !slash_agrees(pattern, req_path, options.trailing_slash) {
         return None;
     }
 
+    let mut p_segs: Vec<&str> = segments(pattern).collect();
+    // Split before decoding so an encoded `%2F` stays inside its segment. The parser
+    // already rejected paths that do not decode.
+    let r_segs: Vec<String> = segments(req_path)
+        .map(|seg| percent_decode(seg).unwrap_or_else(|| seg.to_string()))
+        .collect();
+
+    // An optional last parameter the path leaves out is simply not captured.
+    if p_segs.len() == r_segs.len() + 1
+        && p_segs
+            .last()
+            .and_then(|segment| parse_param(segment))
+            .is_some_and(|param| param.optional)
+    {
+        p_segs.pop();
+    }
+
     let mut out = HashMap::new();
 
+    // A trailing `*name` segment captures the rest of the path, slashes included.
+    let p_segs = match p_segs.split_last() {
+        Some((last, fixed)) if last.starts_with('*') => {
+            let name = &last[1..];
+            if name.is_empty() || r_segs.len() < fixed.len() {
+                return None;
+            }
+            out.insert(name.to_string(), r_segs[fixed.len()..].join("/"));
+            fixed
+        }
+        _ if p_segs.len() != r_segs.len() => return None,
+        _ => &p_segs[..],
+    };
+
     for (ps, rs) in p_segs.iter().zip(r_segs.iter()) {
-        if let Some(name) = ps.strip_prefix(':') {
-            if name.is_empty() {
+        if let Some(param) = parse_param(ps) {
+            if param.name.is_empty() {
                 return None;
             }
-            out.insert(name.to_string(), (*rs).to_string());
+            // Only constrained parameters pay for a regex match.
+            if param
+                .constraint
+                .is_some_and(|re| !segment_regex::is_match(re, rs))
+            {
+                return None;
+            }
+            out.insert(param.name.to_string(), rs.clone());
             continue;
         }
 
-        if ps != rs {
+        let equal = if options.ignore_case {
+            ps.eq_ignore_ascii_case(rs)
+        } else {
+            ps == rs
+        };
+        if !equal {
             return None;
         }
     }
@@ -38,24 +170,113 @@ pub(super) fn match_pattern(pattern: &str, req_path: &str) -> Option<HashMap<Str
     Some(out)
 }
 
-pub(super) fn parse_query(query: &str) -> HashMap<String, String> {
-    let mut out = HashMap::new();
-    if query.is_empty() {
-        return out;
+/// The `/`-separated segments of a pattern or path, ignoring leading and trailing
+/// slashes. Empty segments in the middle are kept.
+pub(super) fn segments(path: &str) -> impl Iterator<Item = &str> {
+    let trimmed = path.trim_matches('/');
+    trimmed.split('/').filter(move |_| !trimmed.is_empty())
+}
+
+/// Whether `trailing_slash` lets a route registered as `pattern` see `req_path`, going
+/// by their trailing slashes alone.
+pub(super) fn slash_agrees(pattern: &str, req_path: &str, trailing_slash: TrailingSlash) -> bool {
+    trailing_slash != TrailingSlash::Strict
+        || is_wildcard(pattern)
+        || has_trailing_slash(pattern) == has_trailing_slash(req_path)
+}
+
+/// Where `RedirectToNoSlash` sends `req_path` when the route it matched first was
+/// registered as `pattern`: the path without its trailing slashes, unless that route
+/// is a wildcard one.
+pub(super) fn slash_redirect<'a>(pattern: &str, req_path: &'a str) -> Option<&'a str> {
+    if !has_trailing_slash(req_path) || is_wildcard(pattern) {
+        return None;
     }
+    match req_path.trim_end_matches('/') {
+        "" => Some("/"),
+        canonical => Some(canonical),
+    }
+}
+
+pub(super) fn has_trailing_slash(path: &str) -> bool {
+    path.len() > 1 && path.ends_with('/')
+}
 
+/// Whether the pattern's last segment is a `*name` capture.
+pub(super) fn is_wildcard(pattern: &str) -> bool {
+    pattern
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .is_some_and(|segment| segment.starts_with('*'))
+}
+
+/// Every value of every query parameter, in order of appearance. Keys and values
+/// are percent-decoded with `+` read as a space; malformed escapes stay literal, as
+/// browsers leave them.
+pub(crate) fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
     for pair in query.split('&') {
         if pair.is_empty() {
             continue;
         }
-        let (k, v) = match pair.split_once('=') {
-            Some((k, v)) => (k, v),
-            None => (pair, ""),
-        };
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
         if !k.is_empty() {
-            out.insert(k.to_string(), v.to_string());
+            out.entry(decode_query_component(k))
+                .or_default()
+                .push(decode_query_component(v));
         }
     }
-
     out
 }
+
+fn decode_query_component(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| hex_byte(bytes.get(i + 1..i + 3)))
+            .flatten();
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                out.push(byte);
+                i += 2;
+            }
+            (b'+', None) => out.push(b' '),
+            (byte, None) => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Decodes `%XX` escapes in a path segment. `+` stays literal, as it only means a space
+/// in query strings. Returns `None` for malformed escapes or bytes that are not UTF-8.
+pub(super) fn percent_decode(segment: &str) -> Option<String> {
+    if !segment.contains('%') {
+        return Some(segment.to_string());
+    }
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            out.push(hex_byte(bytes.get(i + 1..i + 3))?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// The byte written as two hex digits, e.g. `2F` for `/`.
+fn hex_byte(digits: Option<&[u8]>) -> Option<u8> {
+    let digits = digits?;
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::Route;
+use super::route_matching::{self, MatchOptions, TrailingSlash, percent_decode};
+
+/// The routes of one port, in registration order, indexed by a tree of their path
+/// segments. A lookup walks the tree once instead of trying every pattern; the routes
+/// it reaches are then tried in registration order, exactly as a linear scan would.
+#[derive(Clone, Default)]
+pub(super) struct RouteTable {
+    routes: Vec<Route>,
+    /// Per route, whether it has a `:name(re)` segment. The tree cannot tell whether a
+    /// constraint holds, so those routes are confirmed with `match_pattern`.
+    constrained: Vec<bool>,
+    root: Node,
+}
+
+/// One pattern segment position. Indexes point into `RouteTable::routes`.
+#[derive(Clone, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    /// `statics` keys by their ASCII-lowercased form, for case-insensitive lookups.
+    folded: HashMap<String, Vec<String>>,
+    /// Shared by every `:name` segment at this position; the names stay on the routes.
+    param: Option<Box<Node>>,
+    /// Routes whose pattern ends at this node.
+    routes: Vec<usize>,
+    /// Routes whose pattern continues with a `*name` segment here, which matches
+    /// whatever is left of the path.
+    wildcards: Vec<usize>,
+}
+
+impl RouteTable {
+    /// Adds `route` after the others and returns its index, which never changes.
+    pub(super) fn push(&mut self, route: Route) -> usize {
+        let index = self.routes.len();
+        let mut node = &mut self.root;
+        let mut wildcard = false;
+        let mut constrained = false;
+        for segment in route_matching::segments(&route.pattern) {
+            if segment.starts_with('*') {
+                wildcard = true;
+                break;
+            }
+            node = if let Some(param) = route_matching::parse_param(segment) {
+                constrained |= param.constraint.is_some();
+                if param.optional {
+                    // Also reachable without the segment, which can only be the last.
+                    node.routes.push(index);
+                }
+                node.param.get_or_insert_with(Box::default)
+            } else {
+                let spellings = node.folded.entry(segment.to_ascii_lowercase()).or_default();
+                if !spellings.iter().any(|spelling| spelling == segment) {
+                    spellings.push(segment.to_string());
+                }
+                node.statics.entry(segment.to_string()).or_default()
+            };
+        }
+        if wildcard {
+            node.wildcards.push(index);
+        } else {
+            node.routes.push(index);
+        }
+        self.routes.push(route);
+        self.constrained.push(constrained);
+        index
+    }
+
+    pub(super) fn route_mut(&mut self, index: usize) -> &mut Route {
+        &mut self.routes[index]
+    }
+
+    /// The routes themselves can be changed, but not their patterns, which the tree
+    /// was built from.
+    pub(super) fn routes_mut(&mut self) -> impl Iterator<Item = &mut Route> {
+        self.routes.iter_mut()
+    }
+
+    /// Calls `visit` with the index and route of every route whose pattern matches
+    /// `path`, in no particular order.
+    pub(super) fn for_each_match(
+        &self,
+        path: &str,
+        options: MatchOptions,
+        mut visit: impl FnMut(usize, &Route),
+    ) {
+        // Decoded like `match_pattern` does, but only segments with escapes are copied,
+        // and lowercased up front for a case-insensitive walk.
+        let segments: Vec<Cow<str>> = route_matching::segments(path)
+            .map(|segment| {
+                let mut segment = if segment.contains('%') {
+                    Cow::Owned(percent_decode(segment).unwrap_or_else(|| segment.to_string()))
+                } else {
+                    Cow::Borrowed(segment)
+                };
+                if options.ignore_case && segment.bytes().any(|b| b.is_ascii_uppercase()) {
+                    segment.to_mut().make_ascii_lowercase();
+                }
+                segment
+            })
+            .collect();
+        self.root
+            .walk(&segments, options.ignore_case, &mut |index| {
+                let route = &self.routes[index];
+                if route_matching::slash_agrees(&route.pattern, path, options.trailing_slash)
+                    && (!self.constrained[index]
+                        || route_matching::match_pattern(&route.pattern, path, options).is_some())
+                {
+                    visit(index, route);
+                }
+            });
+    }
+
+    /// The first registered route that matches `path` and that `accept` takes.
+    pub(super) fn first_match(
+        &self,
+        path: &str,
+        options: MatchOptions,
+        accept: impl Fn(&Route) -> bool,
+    ) -> Option<&Route> {
+        let mut first: Option<usize> = None;
+        self.for_each_match(path, options, |index, route| {
+            if first.is_none_or(|first| index < first) && accept(route) {
+                first = Some(index);
+            }
+        });
+        first.map(|index| &self.routes[index])
+    }
+
+    /// Every route that matches `path`, in registration order.
+    pub(super) fn all_matches(&self, path: &str, options: MatchOptions) -> Vec<&Route> {
+        let mut indexes = Vec::new();
+        self.for_each_match(path, options, |index, _| indexes.push(index));
+        indexes.sort_unstable();
+        indexes
+            .into_iter()
+            .map(|index| &self.routes[index])
+            .collect()
+    }
+
+    /// Where `TrailingSlash::RedirectToNoSlash` sends `path`, if anywhere.
+    pub(super) fn slash_redirect<'a>(
+        &self,
+        path: &'a str,
+        options: MatchOptions,
+    ) -> Option<&'a str> {
+        if !route_matching::has_trailing_slash(path) {
+            return None;
+        }
+        let options = MatchOptions {
+            trailing_slash: TrailingSlash::Merge,
+            ..options
+        };
+        let route = self.first_match(path, options, |_| true)?;
+        route_matching::slash_redirect(&route.pattern, path)
+    }
+}
+
+impl Node {
+    /// With `ignore_case`, `segments` must already be lowercased.
+    fn walk(&self, segments: &[Cow<str>], ignore_case: bool, visit: &mut impl FnMut(usize)) {
+        self.wildcards.iter().for_each(|&index| visit(index));
+        let Some((segment, rest)) = segments.split_first() else {
+            self.routes.iter().for_each(|&index| visit(index));
+            return;
+        };
+        if ignore_case {
+            for spelling in self.folded.get(segment.as_ref()).into_iter().flatten() {
+                self.statics[spelling].walk(rest, ignore_case, visit);
+            }
+        } else if let Some(child) = self.statics.get(segment.as_ref()) {
+            child.walk(rest, ignore_case, visit);
+        }
+        if let Some(child) = &self.param {
+            child.walk(rest, ignore_case, visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::https::{HttpMethod, StatusCode, response_with_body};
+
+    fn table(patterns: &[&str]) -> RouteTable {
+        let mut table = RouteTable::default();
+        for pattern in patterns {
+            table.push(Route {
+                methods: vec![HttpMethod::Get],
+                pattern: pattern.to_string(),
+                handler: Arc::new(|req, _| {
+                    response_with_body(&req.version, StatusCode::Ok, "", vec![])
+                }),
+                middleware: Vec::new(),
+            });
+        }
+        table
+    }
+
+    #[test]
+    fn lookups_agree_with_a_linear_scan() {
+        let patterns = [
+            "/",
+            "/users",
+            "/users/:id",
+            "/users/me",
+            "/users/:id/posts/*rest",
+            "/files/*path",
+            "/a//b",
+            "/:any/x",
+            "/Users/Me",
+            r"/posts/:id(\d+)",
+            "/posts/:slug",
+            "/docs/:page?",
+            "/v/:n([0-9]{2})?",
+        ];
+        let table = table(&patterns);
+        let paths = [
+            "/",
+            "",
+            "/users/",
+            "/users/7",
+            "/users/me",
+            "/users/me/posts",
+            "/users/7/posts/2024/01",
+            "/files",
+            "/files/a/b%2Fc",
+            "/a//b",
+            "/a/b",
+            "/users/x",
+            "/USERS/Me",
+            "/Files/A",
+            "/posts/12",
+            "/posts/hello",
+            "/docs",
+            "/docs/intro",
+            "/docs/intro/more",
+            "/v",
+            "/v/42",
+            "/v/4",
+            "/nope/deeper/still",
+        ];
+        let modes = [
+            (TrailingSlash::Merge, false),
+            (TrailingSlash::Strict, false),
+            (TrailingSlash::Merge, true),
+        ];
+        for (trailing_slash, ignore_case) in modes {
+            let options = MatchOptions {
+                trailing_slash,
+                ignore_case,
+            };
+            for path in paths {
+                let scanned: Vec<&str> = patterns
+                    .iter()
+                    .copied()
+                    .filter(|pattern| {
+                        route_matching::match_pattern(pattern, path, options).is_some()
+                    })
+                    .collect();
+                let found: Vec<&str> = table
+                    .all_matches(path, options)
+                    .iter()
+                    .map(|route| route.pattern.as_str())
+                    .collect();
+                assert_eq!(found, scanned, "{path} under {options:?}");
+            }
+        }
+        let first = table.first_match("/users/me", MatchOptions::default(), |_| true);
+        assert_eq!(
+            first.map(|route| route.pattern.as_str()),
+            Some("/users/:id")
+        );
+    }
+}
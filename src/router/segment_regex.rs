@@ -0,0 +1,272 @@
+/// Checks that `re` is a constraint `is_match` understands.
+pub(super) fn check(re: &str) -> Result<(), String> {
+    if re.is_empty() {
+        return Err("empty constraint".to_string());
+    }
+    compile(re).map(|_| ())
+}
+
+/// Whether all of `text` matches the constraint `re` of a `:name(re)` segment. The
+/// pattern text is parsed again on every call rather than kept compiled. Supported:
+/// literals, `.`, classes like `[a-z_]` or `[^0-9]`, the escapes `\d \w \s` and their
+/// negations `\D \W \S` (inside classes too), `\` before any other character to take
+/// it literally, and the quantifiers `* + ? {n} {n,} {n,m}`. Groups and alternation are
+/// not. A constraint `check` rejects matches nothing.
+///
+/// The text is read once, left to right, while tracking every position in the pattern
+/// it could have reached, so a segment the client controls costs time linear in its
+/// length however many quantifiers the constraint has.
+pub(super) fn is_match(re: &str, text: &str) -> bool {
+    let Ok(pieces) = compile(re) else {
+        return false;
+    };
+    // A state is a piece and how many characters it has taken so far. Past `min`, an
+    // unbounded piece's count no longer matters, so it stays at `min`.
+    let mut states = Vec::new();
+    add_state(&pieces, &mut states, (0, 0));
+    for c in text.chars() {
+        let mut next = Vec::new();
+        for &(i, count) in &states {
+            let Some(piece) = pieces.get(i) else {
+                continue;
+            };
+            if piece.max.is_some_and(|max| count == max) || !piece.atom.matches(c) {
+                continue;
+            }
+            let count = match piece.max {
+                Some(_) => count + 1,
+                None => (count + 1).min(piece.min),
+            };
+            add_state(&pieces, &mut next, (i, count));
+        }
+        if next.is_empty() {
+            return false;
+        }
+        states = next;
+    }
+    states.contains(&(pieces.len(), 0))
+}
+
+/// Adds `state` to `states`, along with the states after it that need no more input.
+fn add_state(pieces: &[Piece], states: &mut Vec<(usize, usize)>, state: (usize, usize)) {
+    if states.contains(&state) {
+        return;
+    }
+    states.push(state);
+    let (i, count) = state;
+    if pieces.get(i).is_some_and(|piece| count >= piece.min) {
+        add_state(pieces, states, (i + 1, 0));
+    }
+}
+
+/// One atom with its quantifier.
+struct Piece<'a> {
+    atom: Atom<'a>,
+    min: usize,
+    max: Option<usize>,
+}
+
+fn compile(re: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = re;
+    while !rest.is_empty() {
+        let (atom, after) = parse_atom(rest)?;
+        if let Atom::Class { body, .. } = atom {
+            check_class(body)?;
+        }
+        let ((min, max), after) = parse_quantifier(after)?;
+        pieces.push(Piece { atom, min, max });
+        rest = after;
+    }
+    Ok(pieces)
+}
+
+/// What one character must be.
+enum Atom<'a> {
+    Any,
+    Literal(char),
+    Escape(char),
+    Class { body: &'a str, negated: bool },
+}
+
+impl Atom<'_> {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Atom::Any => true,
+            Atom::Literal(literal) => c == literal,
+            Atom::Escape(escape) => escape_matches(escape, c),
+            Atom::Class { body, negated } => class_matches(body, c) != negated,
+        }
+    }
+}
+
+fn parse_atom(re: &str) -> Result<(Atom<'_>, &str), String> {
+    let mut chars = re.chars();
+    let atom = match chars.next() {
+        None => return Err("expected a character".to_string()),
+        Some('.') => Atom::Any,
+        Some('\\') => Atom::Escape(chars.next().ok_or("trailing backslash")?),
+        Some('[') => {
+            let inner = chars.as_str();
+            let (negated, inner) = match inner.strip_prefix('^') {
+                Some(inner) => (true, inner),
+                None => (false, inner),
+            };
+            let end = class_end(inner).ok_or("unclosed character class")?;
+            return Ok((
+                Atom::Class {
+                    body: &inner[..end],
+                    negated,
+                },
+                &inner[end + 1..],
+            ));
+        }
+        Some(c @ ('*' | '+' | '?' | '{')) => return Err(format!("{c:?} has nothing to repeat")),
+        Some(c @ ('(' | ')' | '|' | '^' | '$' | ']' | '}')) => {
+            return Err(format!("{c:?} is not supported; escape it to match it"));
+        }
+        Some(c) => Atom::Literal(c),
+    };
+    Ok((atom, chars.as_str()))
+}
+
+/// Byte offset of the `]` closing a class whose body starts `inner`.
+fn class_end(inner: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ']' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The bounds of the quantifier at the start of `re`, `{1}` when there is none.
+fn parse_quantifier(re: &str) -> Result<((usize, Option<usize>), &str), String> {
+    let bounds = match re.chars().next() {
+        Some('*') => (0, None),
+        Some('+') => (1, None),
+        Some('?') => (0, Some(1)),
+        Some('{') => {
+            let end = re.find('}').ok_or("unclosed repetition")?;
+            let bad = || format!("bad repetition {:?}", &re[..=end]);
+            let number = |n: &str| n.parse::<usize>().map_err(|_| bad());
+            let bounds = match re[1..end].split_once(',') {
+                None => {
+                    let n = number(&re[1..end])?;
+                    (n, Some(n))
+                }
+                Some((min, "")) => (number(min)?, None),
+                Some((min, max)) => (number(min)?, Some(number(max)?)),
+            };
+            if bounds.1.is_some_and(|max| max < bounds.0) {
+                return Err(bad());
+            }
+            return Ok((bounds, &re[end + 1..]));
+        }
+        _ => return Ok(((1, Some(1)), re)),
+    };
+    Ok((bounds, &re[1..]))
+}
+
+fn escape_matches(escape: char, c: char) -> bool {
+    match escape {
+        'd' => c.is_ascii_digit(),
+        'D' => !c.is_ascii_digit(),
+        'w' => c.is_alphanumeric() || c == '_',
+        'W' => !(c.is_alphanumeric() || c == '_'),
+        's' => c.is_whitespace(),
+        'S' => !c.is_whitespace(),
+        literal => c == literal,
+    }
+}
+
+/// Whether `c` is one of the characters, ranges or escapes listed in `body`.
+fn class_matches(body: &str, c: char) -> bool {
+    let mut items = body.chars().peekable();
+    while let Some(item) = items.next() {
+        let found = if item == '\\' {
+            items.next().is_some_and(|escape| escape_matches(escape, c))
+        } else if items.peek() == Some(&'-') && items.clone().nth(1).is_some() {
+            items.next();
+            let last = items.next().unwrap_or(item);
+            (item..=last).contains(&c)
+        } else {
+            c == item
+        };
+        if found {
+            return true;
+        }
+    }
+    false
+}
+
+fn check_class(body: &str) -> Result<(), String> {
+    if body.is_empty() {
+        return Err("empty character class".to_string());
+    }
+    let mut items = body.chars().peekable();
+    while let Some(item) = items.next() {
+        if item == '\\' {
+            items.next().ok_or("trailing backslash")?;
+        } else if items.peek() == Some(&'-') && items.clone().nth(1).is_some() {
+            items.next();
+            let last = items.next().unwrap_or(item);
+            if last < item {
+                return Err(format!("range {item}-{last} is out of order"));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, is_match};
+
+    #[test]
+    fn matches_whole_segments() {
+        let cases = [
+            (r"\d+", "2024", true),
+            (r"\d+", "20a4", false),
+            (r"\d+", "", false),
+            (r"\d{4}-\d{2}", "2024-01", true),
+            (r"\d{4}-\d{2}", "202-01", false),
+            (r"[a-z0-9_-]{1,8}", "my-slug", true),
+            (r"[a-z0-9_-]{1,8}", "much-too-long", false),
+            (r"[^.]+\.json", "data.json", true),
+            (r"[^.]+\.json", "data.xml", false),
+            (r"v\d?", "v", true),
+            (r"a*ab", "aaab", true),
+            (r"\w+", "naïve_1", true),
+            (r".*", "", true),
+            (r"\(x\)", "(x)", true),
+        ];
+        for (re, text, expected) in cases {
+            assert!(check(re).is_ok(), "{re}");
+            assert_eq!(is_match(re, text), expected, "{re} on {text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_interpret() {
+        for re in [
+            "", "+a", "a**", "(a|b)", "[a-", "[]", "[z-a]", r"a\", "a{2,1}", "a{x}",
+        ] {
+            assert!(check(re).is_err(), "{re}");
+        }
+    }
+
+    #[test]
+    fn long_segments_fail_in_linear_time() {
+        let text = "a".repeat(10_000);
+        let started = std::time::Instant::now();
+        assert!(!is_match(r"\w*\w*\w*x", &text));
+        assert!(!is_match(r"a*a*a*a*a*b", &text));
+        assert!(is_match(r"\w*\w*\w*a", &text));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
@@ -1,12 +1,12 @@
 use std::collections::HashMap;
-use std::time::Instant;
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
 
 use rand::RngCore;
-use rand::rngs::OsRng;
 
-use crate::https::Request;
+use crate::https::{Request, Response, StatusCode};
 
-use super::{SESSION_TTL, Session};
+use super::{Router, Session, SessionWrite};
 
 fn parse_cookie_header(cookie: &str) -> HashMap<String, String> {
     let mut out = HashMap::new();
@@ -26,27 +26,24 @@ fn parse_cookie_header(cookie: &str) -> HashMap<String, String> {
     out
 }
 
-fn generate_session_id() -> String {
+fn generate_session_id(rng: &mut dyn RngCore) -> String {
     let mut bytes = [0u8; 32];
-    OsRng.fill_bytes(&mut bytes);
+    rng.fill_bytes(&mut bytes);
     hex::encode(bytes)
 }
 
+fn cookie_session_id(req: &Request) -> Option<String> {
+    let raw_cookie = req.headers.get("cookie")?;
+    parse_cookie_header(raw_cookie).remove("sid")
+}
+
 pub(super) fn resolve_session(
     sessions: &mut HashMap<String, Session>,
+    rng: &mut dyn RngCore,
     req: &Request,
     now: Instant,
 ) -> (Option<String>, bool) {
-    let mut cookie_sid: Option<String> = None;
-
-    if let Some(raw_cookie) = req.headers.get("cookie") {
-        let cookies = parse_cookie_header(raw_cookie);
-        if let Some(sid) = cookies.get("sid") {
-            cookie_sid = Some(sid.clone());
-        }
-    }
-
-    if let Some(sid) = cookie_sid
+    if let Some(sid) = cookie_session_id(req)
         && let Some(sess) = sessions.get_mut(&sid)
     {
         sess.last_seen = now;
@@ -54,7 +51,7 @@ pub(super) fn resolve_session(
         return (Some(sid), false);
     }
 
-    let sid = generate_session_id();
+    let sid = generate_session_id(rng);
     sessions.insert(
         sid.clone(),
         Session {
@@ -62,12 +59,93 @@ pub(super) fn resolve_session(
             created_at: now,
             last_seen: now,
             visits: 1,
+            data: HashMap::new(),
         },
     );
 
     (Some(sid), true)
 }
 
-pub(super) fn cleanup_expired_sessions(sessions: &mut HashMap<String, Session>, now: Instant) {
-    sessions.retain(|_, s| now.duration_since(s.last_seen) <= SESSION_TTL);
+pub(super) fn apply_session_writes(session: &mut Session, writes: Vec<SessionWrite>) {
+    for write in writes {
+        match write {
+            SessionWrite::Set(key, value) => {
+                session.data.insert(key, value);
+            }
+            SessionWrite::Remove(key) => {
+                session.data.remove(&key);
+            }
+        }
+    }
+}
+
+pub(super) fn cleanup_expired_sessions(
+    sessions: &mut HashMap<String, Session>,
+    now: Instant,
+    ttl: Duration,
+) {
+    sessions.retain(|_, s| now.duration_since(s.last_seen) <= ttl);
+}
+
+impl Router {
+    /// Number of live sessions.
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Removes every session for which `predicate` returns true and returns how many
+    /// were removed. Clients presenting a removed sid get a fresh session next time.
+    pub fn invalidate_sessions_where<P>(&mut self, mut predicate: P) -> usize
+    where
+        P: FnMut(&Session) -> bool,
+    {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, s| !predicate(s));
+        before - self.sessions.len()
+    }
+
+    /// Replaces the randomness behind new session ids (`OsRng` by default), e.g. with
+    /// a seeded generator so tests can predict the ids they get.
+    pub fn set_session_rng(&mut self, rng: impl RngCore + 'static) -> &mut Self {
+        self.session_rng = Box::new(rng);
+        self
+    }
+
+    /// Caps how many connections may carry the same session cookie at once
+    /// (unlimited by default). Requests over the cap get `429 Too Many Requests`.
+    pub fn set_max_conns_per_session(&mut self, max: usize) -> &mut Self {
+        self.max_conns_per_session = Some(max);
+        self
+    }
+
+    /// Binds connection `fd` to the session named by the request's cookie and
+    /// returns a 429 response when that session already has too many connections.
+    pub(super) fn admit_session(&mut self, fd: RawFd, req: &Request) -> Option<Response> {
+        let sid = cookie_session_id(req).filter(|sid| self.sessions.contains_key(sid))?;
+        let conn = self.conns.get_mut(&fd)?;
+        if conn.session_id.as_deref() == Some(sid.as_str()) {
+            return None;
+        }
+
+        let active = self.session_conns.get(&sid).copied().unwrap_or(0);
+        if self.max_conns_per_session.is_some_and(|max| active >= max) {
+            eprintln!("session connection cap reached fd={fd} active={active}");
+            return Some(self.render_error(Some(req), StatusCode::TooManyRequests));
+        }
+
+        if let Some(previous) = conn.session_id.replace(sid.clone()) {
+            release_session_conn(&mut self.session_conns, &previous);
+        }
+        *self.session_conns.entry(sid).or_insert(0) += 1;
+        None
+    }
+}
+
+pub(super) fn release_session_conn(session_conns: &mut HashMap<String, usize>, sid: &str) {
+    if let Some(count) = session_conns.get_mut(sid) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            session_conns.remove(sid);
+        }
+    }
 }
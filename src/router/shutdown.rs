@@ -0,0 +1,137 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+use crate::utils::helpers::{
+    close_fd, create_signal_fd, epoll_add, epoll_del, epoll_wait_timeout, read_signals,
+    unblock_signals,
+};
+
+use super::{CloseReason, Router};
+
+pub(super) const SHUTDOWN_SIGNALS: [i32; 2] = [libc::SIGTERM, libc::SIGINT];
+
+/// How long `run` and `run_workers` keep flushing responses after a shutdown signal.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+impl Router {
+    /// Serves until SIGTERM or SIGINT arrives, then stops accepting and reading,
+    /// flushes queued responses for up to 10 seconds (see `drain`), and closes the
+    /// listeners and the epoll instance. Returns `Ok(())` after such a shutdown.
+    ///
+    /// The signals are blocked on the calling thread and read through a `signalfd`
+    /// in the event loop; a second signal during the flush closes the remaining
+    /// connections at once.
+    pub fn run(&mut self) -> io::Result<()> {
+        self.watch_shutdown_signals()?;
+        while !self.shutdown_requested {
+            if let Err(err) = self.handle_connections() {
+                eprintln!("server loop error: {err}");
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        self.finish_shutdown();
+        Ok(())
+    }
+
+    /// Drains the connections (see `drain`), then releases the signal fd, if any, and
+    /// the epoll instance.
+    pub(super) fn finish_shutdown(&mut self) {
+        crate::info!("shutting down", "connections" => self.conns.len());
+        let forced = self.drain(SHUTDOWN_GRACE);
+        if forced > 0 {
+            crate::warn!("closed connections with unsent responses", "count" => forced);
+        }
+        if let Some(signal_fd) = self.signal_fd.take() {
+            epoll_del(self.epfd, signal_fd);
+            close_fd(signal_fd);
+            unblock_signals(&SHUTDOWN_SIGNALS);
+        }
+        close_fd(self.epfd);
+        self.epfd = -1;
+    }
+
+    /// Starts routing SIGTERM and SIGINT into the event loop, if not done already.
+    pub(super) fn watch_shutdown_signals(&mut self) -> io::Result<()> {
+        if self.signal_fd.is_some() {
+            return Ok(());
+        }
+        let signal_fd = create_signal_fd(&SHUTDOWN_SIGNALS)?;
+        if let Err(e) = epoll_add(self.epfd, signal_fd, libc::EPOLLIN as u32) {
+            close_fd(signal_fd);
+            unblock_signals(&SHUTDOWN_SIGNALS);
+            return Err(e);
+        }
+        self.signal_fd = Some(signal_fd);
+        Ok(())
+    }
+
+    /// Consumes the pending signals and asks `run` to stop.
+    pub(super) fn handle_signal_ready(&mut self, signal_fd: RawFd) {
+        for signal in read_signals(signal_fd) {
+            crate::info!("received shutdown signal", "signal" => signal);
+            self.shutdown_requested = true;
+        }
+    }
+
+    /// Shuts the server down cleanly: stops accepting, stops reading requests, and
+    /// keeps flushing queued responses until every connection has closed or `timeout`
+    /// has passed. Connections still sending at the deadline (or when a second
+    /// shutdown signal arrives during `run`) are closed with
+    /// `CloseReason::DrainTimeout`; their number is returned.
+    ///
+    /// The listeners are closed, so the router cannot serve again afterwards.
+    pub fn drain(&mut self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+
+        for (listen_fd, _) in self.listeners.drain() {
+            epoll_del(self.epfd, listen_fd);
+            close_fd(listen_fd);
+        }
+        for (client_fd, _, peer_addr) in self.pending_accepts.drain(..) {
+            if let Some(peer) = peer_addr {
+                super::conn_limits::release_ip_conn(&mut self.ip_conns, peer.ip());
+            }
+            close_fd(client_fd);
+        }
+        let fds: Vec<RawFd> = self.conns.keys().copied().collect();
+        for fd in fds {
+            self.close_connection(fd);
+        }
+
+        'flush: while !self.conns.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let timeout_ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            let n = match epoll_wait_timeout(self.epfd, &mut self.events, timeout_ms) {
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("drain wait failed: {e}");
+                    break;
+                }
+            };
+            for i in 0..n {
+                let (fd, flags) = {
+                    let ev = &self.events[i];
+                    (ev.u64 as RawFd, ev.events)
+                };
+                if self.signal_fd == Some(fd) {
+                    // Another signal while flushing: give up on the remaining responses.
+                    read_signals(fd);
+                    break 'flush;
+                }
+                // Every connection is armed for writes only, so this just flushes.
+                self.handle_client_event(fd, flags);
+            }
+        }
+
+        let stuck: Vec<RawFd> = self.conns.keys().copied().collect();
+        for &fd in &stuck {
+            self.drop_conn(fd, CloseReason::DrainTimeout);
+        }
+        stuck.len()
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::sync::Arc;
+
+use libc::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
+
+use crate::conn::{Conn, ConnState};
+use crate::https::{Body, HeaderMap, Request, Response, StatusCode};
+use crate::utils::helpers::epoll_mod;
+
+use super::{CloseReason, Data, Router, route_matching};
+
+/// State machine that owns a connection after a `101 Switching Protocols` handshake.
+///
+/// The router keeps doing the socket I/O: it appends received bytes to `conn.in_buf`
+/// and flushes whatever the handler leaves in `conn.out_buf` once the fd is writable.
+pub trait UpgradedHandler {
+    /// Called after new bytes were appended to `conn.in_buf`. The handler drains the
+    /// bytes it consumed and queues replies in `conn.out_buf`.
+    fn on_readable(&mut self, conn: &mut Conn) -> UpgradeStatus;
+}
+
+pub enum UpgradeStatus {
+    Continue,
+    /// Flush any queued bytes, then close the connection.
+    Close,
+}
+
+pub enum UpgradeOutcome {
+    /// Send `response` (expected to be a 101) and hand the connection to the handler.
+    Accept(Response, Box<dyn UpgradedHandler>),
+    /// Answer with a regular HTTP response; the connection is not upgraded.
+    Reject(Response),
+}
+
+pub type UpgradeRouteHandler = Arc<dyn Fn(&Request, &Data) -> UpgradeOutcome + Send + Sync>;
+
+#[derive(Clone)]
+pub struct UpgradeRoute {
+    pub pattern: String,
+    pub handler: UpgradeRouteHandler,
+}
+
+pub fn switching_protocols(version: &str, protocol: &str) -> Response {
+    let mut headers = HeaderMap::default();
+    headers.insert("Upgrade", protocol);
+    headers.insert("Connection", "Upgrade");
+
+    Response {
+        version: version.to_string(),
+        status: StatusCode::SwitchingProtocols,
+        headers,
+        body: Body::default(),
+        stream: None,
+        raw: None,
+        file: None,
+    }
+}
+
+fn wants_upgrade(req: &Request) -> bool {
+    let has_token = req.headers.get("connection").is_some_and(|v| {
+        v.split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    has_token && req.headers.get("upgrade").is_some()
+}
+
+impl Router {
+    pub fn add_upgrade_route<H>(&mut self, port: u16, pattern: &str, handler: H)
+    where
+        H: Fn(&Request, &Data) -> UpgradeOutcome + Send + Sync + 'static,
+    {
+        route_matching::assert_valid_pattern(pattern);
+        self.upgrade_routes
+            .entry(port)
+            .or_default()
+            .push(UpgradeRoute {
+                pattern: pattern.to_string(),
+                handler: Arc::new(handler),
+            });
+    }
+
+    /// Runs the matching upgrade route, if the request asks for an upgrade and one exists.
+    pub(super) fn handle_upgrade(
+        &self,
+        local_port: u16,
+        req: &Request,
+    ) -> Option<(Response, Option<Box<dyn UpgradedHandler>>)> {
+        if !wants_upgrade(req) {
+            return None;
+        }
+
+        let routes = self.upgrade_routes.get(&local_port)?;
+        let (handler, path_value) = routes.iter().find_map(|route| {
+            route_matching::match_pattern(&route.pattern, &req.path, self.match_options)
+                .map(|path_value| (route.handler.clone(), path_value))
+        })?;
+
+        let data = Data {
+            path_value,
+            body: req.data.body.clone(),
+            peer_addr: self.current_peer,
+            state: self.state.clone(),
+            ..Data::with_query(&req.query)
+        };
+
+        match handler(req, &data) {
+            UpgradeOutcome::Accept(resp, protocol) => {
+                if !matches!(resp.status, StatusCode::SwitchingProtocols) {
+                    eprintln!(
+                        "upgrade handler for {} accepted without a 101 response",
+                        req.path
+                    );
+                    return Some((resp, None));
+                }
+                Some((resp, Some(protocol)))
+            }
+            UpgradeOutcome::Reject(resp) => Some((resp, None)),
+        }
+    }
+
+    /// Switches a connection whose 101 response has been flushed over to its handler.
+    pub(super) fn start_upgraded(&mut self, fd: RawFd) -> io::Result<()> {
+        let c = self
+            .conns
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "conn missing"))?;
+        c.state = ConnState::Upgraded;
+
+        if c.in_buf.is_empty() {
+            let mask = self
+                .config
+                .client_mask(EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP);
+            return epoll_mod(self.epfd, fd, mask);
+        }
+        self.drive_upgraded(fd)
+    }
+
+    /// Lets the upgraded handler consume buffered input and re-arms epoll for its output.
+    pub(super) fn drive_upgraded(&mut self, fd: RawFd) -> io::Result<()> {
+        let (Some(c), Some(handler)) = (self.conns.get_mut(&fd), self.upgrades.get_mut(&fd)) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "conn missing"));
+        };
+
+        if let UpgradeStatus::Close = handler.on_readable(c) {
+            self.upgrades.remove(&fd);
+            c.state = ConnState::Responding;
+            if c.out_buf.is_empty() {
+                self.drop_conn(fd, CloseReason::Completed);
+                return Ok(());
+            }
+        }
+
+        let mut events = EPOLLIN | EPOLLRDHUP | EPOLLERR | EPOLLHUP;
+        if !c.out_buf.is_empty() {
+            events |= EPOLLOUT;
+        }
+        epoll_mod(self.epfd, fd, self.config.client_mask(events))
+    }
+}
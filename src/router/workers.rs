@@ -0,0 +1,232 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::conn::ParseOptions;
+use crate::https::{CompressionFilter, LineEnding};
+use crate::utils::helpers::{
+    close_fd, create_signal_fd, epoll_del, poll_readable, read_signals, unblock_signals,
+};
+
+use super::metrics::Counters;
+use super::route_matching::MatchOptions;
+use super::route_tree::RouteTable;
+use super::shutdown::SHUTDOWN_SIGNALS;
+use super::{
+    AccessLogWriter, CorsConfig, ErrorRenderer, Handler, ListenAddr, Middleware, Preprocessor,
+    Router, RouterConfig, UpgradeRoute,
+};
+
+/// How often the signal-waiting thread checks whether a worker stopped on its own.
+const WORKER_CHECK_MS: i32 = 1000;
+
+/// Everything a worker copies from the configured router. Sessions, connections, the
+/// session rng, the lifecycle observer and the slow-event hook stay behind: they are
+/// per-router state or not `Send`.
+#[derive(Clone)]
+struct WorkerConfig {
+    routes: HashMap<u16, RouteTable>,
+    upgrade_routes: HashMap<u16, Vec<UpgradeRoute>>,
+    request_budget: Option<Duration>,
+    parse_options: ParseOptions,
+    max_conns_per_session: Option<usize>,
+    keep_alive: bool,
+    max_response_body: Option<usize>,
+    newline_normalization: Option<LineEnding>,
+    gzip_min_size: Option<usize>,
+    gzip_filter: CompressionFilter,
+    allowed_hosts: Option<Vec<String>>,
+    server_name: Option<String>,
+    slow_event_threshold: Option<Duration>,
+    preprocessor: Option<Preprocessor>,
+    middleware: Vec<Middleware>,
+    fallbacks: HashMap<u16, Handler>,
+    error_renderers: HashMap<u16, ErrorRenderer>,
+    directory_listing: Arc<AtomicBool>,
+    auto_etag: bool,
+    accept_batch: Option<usize>,
+    header_timeout: Duration,
+    max_conns_per_ip: Option<usize>,
+    max_total_conns: Option<usize>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    access_log: Option<AccessLogWriter>,
+    metrics: Arc<Counters>,
+    cors: Option<CorsConfig>,
+    match_options: MatchOptions,
+    config: RouterConfig,
+}
+
+impl WorkerConfig {
+    fn from_router(router: &Router) -> Self {
+        Self {
+            routes: router.routes.clone(),
+            upgrade_routes: router.upgrade_routes.clone(),
+            request_budget: router.request_budget,
+            parse_options: router.parse_options,
+            max_conns_per_session: router.max_conns_per_session,
+            keep_alive: router.keep_alive,
+            max_response_body: router.max_response_body,
+            newline_normalization: router.newline_normalization,
+            gzip_min_size: router.gzip_min_size,
+            gzip_filter: router.gzip_filter.clone(),
+            allowed_hosts: router.allowed_hosts.clone(),
+            server_name: router.server_name.clone(),
+            slow_event_threshold: router.slow_event_threshold,
+            preprocessor: router.preprocessor.clone(),
+            middleware: router.middleware.clone(),
+            fallbacks: router.fallbacks.clone(),
+            error_renderers: router.error_renderers.clone(),
+            directory_listing: Arc::clone(&router.directory_listing),
+            auto_etag: router.auto_etag,
+            accept_batch: router.accept_batch,
+            header_timeout: router.header_timeout,
+            max_conns_per_ip: router.max_conns_per_ip,
+            max_total_conns: router.max_total_conns,
+            state: router.state.clone(),
+            access_log: router.access_log.clone(),
+            metrics: Arc::clone(&router.metrics),
+            cors: router.cors.clone(),
+            match_options: router.match_options,
+            config: router.config,
+        }
+    }
+
+    /// Builds a router listening on `addrs` through `SO_REUSEPORT` sockets.
+    fn build(self, addrs: &[ListenAddr]) -> io::Result<Router> {
+        let mut router = Router::with_listeners(addrs, true, self.config);
+        if router.listeners.len() != addrs.len() {
+            close_fd(router.epfd);
+            for &listen_fd in router.listeners.keys() {
+                close_fd(listen_fd);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "worker could not bind every address",
+            ));
+        }
+
+        router.routes = self.routes;
+        router.upgrade_routes = self.upgrade_routes;
+        router.request_budget = self.request_budget;
+        router.parse_options = self.parse_options;
+        router.max_conns_per_session = self.max_conns_per_session;
+        router.keep_alive = self.keep_alive;
+        router.max_response_body = self.max_response_body;
+        router.newline_normalization = self.newline_normalization;
+        router.gzip_min_size = self.gzip_min_size;
+        router.gzip_filter = self.gzip_filter;
+        router.allowed_hosts = self.allowed_hosts;
+        router.server_name = self.server_name;
+        router.slow_event_threshold = self.slow_event_threshold;
+        router.preprocessor = self.preprocessor;
+        router.middleware = self.middleware;
+        router.fallbacks = self.fallbacks;
+        router.error_renderers = self.error_renderers;
+        router.directory_listing = self.directory_listing;
+        router.auto_etag = self.auto_etag;
+        router.accept_batch = self.accept_batch;
+        router.header_timeout = self.header_timeout;
+        router.max_conns_per_ip = self.max_conns_per_ip;
+        router.max_total_conns = self.max_total_conns;
+        router.state = self.state;
+        router.access_log = self.access_log;
+        router.metrics = self.metrics;
+        router.cors = self.cors;
+        router.match_options = self.match_options;
+        Ok(router)
+    }
+}
+
+fn serve_worker(config: WorkerConfig, addrs: &[ListenAddr], stop: &AtomicBool) -> io::Result<()> {
+    let mut router = config.build(addrs)?;
+    while !stop.load(Ordering::Relaxed) {
+        if let Err(err) = router.handle_connections() {
+            eprintln!("worker loop error: {err}");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+    router.finish_shutdown();
+    Ok(())
+}
+
+impl Router {
+    /// Serves this router's addresses from `workers` threads until SIGTERM or SIGINT, then
+    /// shuts every worker down like `run` does.
+    ///
+    /// Each worker has its own epoll instance and its own `SO_REUSEPORT` listeners on
+    /// the same addresses, and the kernel spreads new connections over them. Workers get
+    /// copies of the routes and settings, but not the lifecycle observer, slow-event
+    /// hook or session rng, which are not `Send`. Sessions are per worker: a cookie
+    /// issued by one worker is unknown to the others, so a client whose connections
+    /// land on different workers gets a new session on each. The state from `set_state`
+    /// the access log writer and the `metrics` counters are the exception: workers share
+    /// them rather than copy them.
+    ///
+    /// This router's own listeners are closed first, since they lack `SO_REUSEPORT`.
+    /// A worker notices shutdown within a second, on its next loop iteration.
+    pub fn run_workers(&mut self, workers: usize) -> io::Result<()> {
+        assert!(workers > 0, "run_workers needs at least one worker");
+        let addrs: Vec<ListenAddr> = self.listeners.values().copied().collect();
+        let config = WorkerConfig::from_router(self);
+        for (listen_fd, _) in self.listeners.drain() {
+            epoll_del(self.epfd, listen_fd);
+            close_fd(listen_fd);
+        }
+
+        // Blocked before spawning so that workers inherit the mask and the signals are
+        // left for this thread's signalfd.
+        let signal_fd = create_signal_fd(&SHUTDOWN_SIGNALS)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handles: Vec<JoinHandle<io::Result<()>>> = Vec::with_capacity(workers);
+        let mut result = Ok(());
+        for id in 0..workers {
+            let config = config.clone();
+            let addrs = addrs.clone();
+            let stop = Arc::clone(&stop);
+            let spawned = thread::Builder::new()
+                .name(format!("http-worker-{id}"))
+                .spawn(move || serve_worker(config, &addrs, &stop));
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        crate::info!("started workers", "workers" => handles.len(), "addresses" => addrs.len());
+
+        while result.is_ok() && !handles.iter().any(JoinHandle::is_finished) {
+            match poll_readable(signal_fd, WORKER_CHECK_MS) {
+                Ok(true) => {
+                    for signal in read_signals(signal_fd) {
+                        crate::info!("received shutdown signal", "signal" => signal);
+                    }
+                    break;
+                }
+                Ok(false) => {}
+                Err(e) => result = Err(e),
+            }
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        for handle in handles {
+            let outcome = handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("worker panicked")));
+            if let Err(e) = outcome {
+                crate::error!("worker failed", "error" => &e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        close_fd(signal_fd);
+        unblock_signals(&SHUTDOWN_SIGNALS);
+        result
+    }
+}
@@ -1,9 +1,10 @@
-use std::{io, mem, net::Ipv4Addr, os::fd::RawFd};
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::os::fd::RawFd;
 
 use libc::epoll_event;
 
-const EPOLL_WAIT_MS: i32 = 1000;
-
 fn is_would_block(e: &io::Error) -> bool {
     matches!(
         e.raw_os_error(),
@@ -11,13 +12,17 @@ fn is_would_block(e: &io::Error) -> bool {
     )
 }
 
-pub fn accept_nonblocking(listen_fd: RawFd) -> io::Result<Option<RawFd>> {
+/// Accepts one pending connection, returning its fd and the peer's address. The
+/// address is `None` for peers that are not IP sockets.
+pub fn accept_nonblocking(listen_fd: RawFd) -> io::Result<Option<(RawFd, Option<SocketAddr>)>> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
     // accept4 with libc::SOCK_NONBLOCK so the client libc::socket is nonblocking too.
     let fd = unsafe {
         libc::accept4(
             listen_fd,
-            std::ptr::null_mut(),
-            std::ptr::null_mut(),
+            &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
             libc::SOCK_NONBLOCK,
         )
     };
@@ -25,7 +30,7 @@ pub fn accept_nonblocking(listen_fd: RawFd) -> io::Result<Option<RawFd>> {
         let e = io::Error::last_os_error();
         if is_would_block(&e) { Ok(None) } else { Err(e) }
     } else {
-        Ok(Some(fd))
+        Ok(Some((fd, from_raw_sockaddr(&storage))))
     }
 }
 
@@ -39,6 +44,24 @@ pub fn recv_nonblocking(fd: RawFd, buf: &mut [u8]) -> io::Result<Option<usize>>
     }
 }
 
+/// Copies up to `count` bytes of `in_fd`, starting at `offset`, to the socket `out_fd`
+/// inside the kernel. `Ok(None)` means the socket is full.
+pub fn sendfile_nonblocking(
+    out_fd: RawFd,
+    in_fd: RawFd,
+    offset: u64,
+    count: usize,
+) -> io::Result<Option<usize>> {
+    let mut offset = offset as libc::off_t;
+    let n = unsafe { libc::sendfile(out_fd, in_fd, &mut offset, count) };
+    if n < 0 {
+        let e = io::Error::last_os_error();
+        if is_would_block(&e) { Ok(None) } else { Err(e) }
+    } else {
+        Ok(Some(n as usize))
+    }
+}
+
 pub fn send_nonblocking(fd: RawFd, buf: &[u8]) -> io::Result<Option<usize>> {
     // MSG_NOSIGNAL avoids SIGPIPE on some systems when peer closed.
     let n = unsafe {
@@ -101,10 +124,46 @@ pub fn last_err(ctx: &str) -> io::Error {
     )
 }
 
-pub fn create_listen_socket(port: u16) -> io::Result<RawFd> {
+/// Kernel settings for listening sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketConfig {
+    /// Length of the queue of connections waiting for `accept`. The kernel silently
+    /// caps it at `net.core.somaxconn`.
+    pub backlog: i32,
+    /// `SO_RCVBUF` in bytes, inherited by accepted sockets; `None` keeps the kernel's
+    /// default and autotuning. Linux doubles the value and caps it at `rmem_max`.
+    pub recv_buffer: Option<usize>,
+    /// `SO_SNDBUF` in bytes, like `recv_buffer`; capped at `wmem_max`.
+    pub send_buffer: Option<usize>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            backlog: 1024,
+            recv_buffer: None,
+            send_buffer: None,
+        }
+    }
+}
+
+/// Opens a non-blocking listener on `addr`. With `reuse_port` it sets `SO_REUSEPORT`,
+/// so several such sockets can share the port and the kernel spreads incoming
+/// connections over them. `v6_only` applies to IPv6 addresses: when false the socket
+/// also accepts IPv4 clients as v4-mapped addresses.
+pub fn create_listen_socket(
+    addr: &SocketAddr,
+    reuse_port: bool,
+    v6_only: bool,
+    config: &SocketConfig,
+) -> io::Result<RawFd> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
     let fd = unsafe {
         // libc::SOCK_NONBLOCK here means the listening libc::socket is nonblocking.
-        let fd = libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_NONBLOCK, 0);
+        let fd = libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK, 0);
         if fd < 0 {
             return Err(last_err("libc::socket"));
         }
@@ -112,35 +171,54 @@ pub fn create_listen_socket(port: u16) -> io::Result<RawFd> {
     };
 
     // SO_REUSEADDR so you can restart quickly after Ctrl+C.
-    let yes: i32 = 1;
-    let rc = unsafe {
-        libc::setsockopt(
-            fd,
-            libc::SOL_SOCKET,
-            libc::SO_REUSEADDR,
-            &yes as *const _ as *const libc::c_void,
-            mem::size_of::<i32>() as u32,
-        )
-    };
-    if rc < 0 {
-        close_fd(fd);
-        return Err(last_err("libc::setsockopt(SO_REUSEADDR)"));
-    }
-    // TODO: parse the address string to port and IP (NO NEED)
-    let addr = libc::sockaddr_in {
-        sin_family: libc::AF_INET as u16,
-        sin_port: port.to_be(), // network byte order // TODO: pass the port (NO NEED)
-        sin_addr: libc::in_addr {
-            s_addr: u32::from(Ipv4Addr::UNSPECIFIED).to_be(), // 0.0.0.0 // TODO: pass the host (NO NEED)
-        },
-        sin_zero: [0; 8],
-    };
+    let mut options = vec![(libc::SOL_SOCKET, libc::SO_REUSEADDR, 1, "SO_REUSEADDR")];
+    if reuse_port {
+        options.push((libc::SOL_SOCKET, libc::SO_REUSEPORT, 1, "SO_REUSEPORT"));
+    }
+    if addr.is_ipv6() {
+        // Set explicitly: the kernel default comes from net.ipv6.bindv6only.
+        let value = i32::from(v6_only);
+        options.push((libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, value, "IPV6_V6ONLY"));
+    }
+    let buffers = [
+        (libc::SO_RCVBUF, config.recv_buffer, "SO_RCVBUF"),
+        (libc::SO_SNDBUF, config.send_buffer, "SO_SNDBUF"),
+    ];
+    for (name, size, label) in buffers {
+        let Some(size) = size else {
+            continue;
+        };
+        let Ok(value) = i32::try_from(size) else {
+            close_fd(fd);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{label} of {size} bytes is too large"),
+            ));
+        };
+        options.push((libc::SOL_SOCKET, name, value, label));
+    }
+    for (level, name, value, label) in options {
+        let rc = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const i32 as *const libc::c_void,
+                mem::size_of::<i32>() as u32,
+            )
+        };
+        if rc < 0 {
+            close_fd(fd);
+            return Err(last_err(&format!("libc::setsockopt({label})")));
+        }
+    }
 
+    let (storage, len) = to_raw_sockaddr(addr);
     let rc = unsafe {
         libc::bind(
             fd,
-            &addr as *const _ as *const libc::sockaddr,
-            mem::size_of::<libc::sockaddr_in>() as u32,
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len,
         )
     };
     if rc < 0 {
@@ -148,7 +226,7 @@ pub fn create_listen_socket(port: u16) -> io::Result<RawFd> {
         return Err(last_err("bind"));
     }
 
-    let rc = unsafe { libc::listen(fd, 1024) };
+    let rc = unsafe { libc::listen(fd, config.backlog) };
     if rc < 0 {
         close_fd(fd);
         return Err(last_err("listen"));
@@ -157,6 +235,73 @@ pub fn create_listen_socket(port: u16) -> io::Result<RawFd> {
     Ok(fd)
 }
 
+/// Reads the `sockaddr_in` or `sockaddr_in6` in `storage`. IPv4 clients of a
+/// dual-stack listener arrive v4-mapped (`::ffff:a.b.c.d`) and come back as IPv4.
+fn from_raw_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match i32::from(storage.ss_family) {
+        libc::AF_INET => {
+            let raw =
+                unsafe { *(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(raw.sin_addr.s_addr));
+            Some(SocketAddr::from((ip, u16::from_be(raw.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let raw =
+                unsafe { *(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(raw.sin6_addr.s6_addr);
+            let port = u16::from_be(raw.sin6_port);
+            Some(match ip.to_ipv4_mapped() {
+                Some(v4) => SocketAddr::from((v4, port)),
+                None => SocketAddr::V6(SocketAddrV6::new(
+                    ip,
+                    port,
+                    raw.sin6_flowinfo,
+                    raw.sin6_scope_id,
+                )),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// `addr` as a `sockaddr_storage` holding a `sockaddr_in` or `sockaddr_in6`, plus the
+/// length of the part in use.
+fn to_raw_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let raw = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: v4.port().to_be(), // network byte order
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*v4.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                (&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in).write(raw)
+            };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let raw = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: v6.port().to_be(),
+                sin6_flowinfo: v6.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: v6.ip().octets(),
+                },
+                sin6_scope_id: v6.scope_id(),
+            };
+            unsafe {
+                (&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6).write(raw)
+            };
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
 pub fn should_drop(flags: u32) -> bool {
     (flags & (libc::EPOLLERR as u32)) != 0
         || (flags & (libc::EPOLLHUP as u32)) != 0
@@ -171,23 +316,160 @@ pub fn create_epoll() -> io::Result<RawFd> {
     Ok(epfd)
 }
 
-pub fn epoll_wait_blocking(epfd: RawFd, events: &mut [epoll_event]) -> io::Result<usize> {
+/// `epoll_wait` with an explicit timeout in milliseconds; 0 polls without blocking.
+pub fn epoll_wait_timeout(
+    epfd: RawFd,
+    events: &mut [epoll_event],
+    timeout_ms: i32,
+) -> io::Result<usize> {
+    loop {
+        let n =
+            unsafe { libc::epoll_wait(epfd, events.as_mut_ptr(), events.len() as i32, timeout_ms) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(last_err("epoll_wait"));
+        }
+        return Ok(n as usize);
+    }
+}
+
+fn signal_set(signals: &[i32]) -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &signal in signals {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    }
+}
+
+/// Blocks `signals` for the calling thread and returns a non-blocking `signalfd`
+/// that becomes readable when one of them is pending.
+pub fn create_signal_fd(signals: &[i32]) -> io::Result<RawFd> {
+    let set = signal_set(signals);
+    let rc = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    let fd = unsafe { libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(last_err("signalfd"));
+    }
+    Ok(fd)
+}
+
+/// Restores default delivery of `signals` blocked by `create_signal_fd`.
+pub fn unblock_signals(signals: &[i32]) {
+    let set = signal_set(signals);
+    unsafe { libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut()) };
+}
+
+/// Consumes every pending signal on `signal_fd` and returns their numbers.
+pub fn read_signals(signal_fd: RawFd) -> Vec<u32> {
+    let mut signals = Vec::new();
     loop {
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::signalfd_siginfo>();
         let n = unsafe {
-            libc::epoll_wait(
-                epfd,
-                events.as_mut_ptr(),
-                events.len() as i32,
-                EPOLL_WAIT_MS,
+            libc::read(
+                signal_fd,
+                &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                size,
             )
         };
+        if n as usize != size {
+            return signals;
+        }
+        signals.push(info.ssi_signo);
+    }
+}
+
+/// Waits up to `timeout_ms` for `fd` to become readable.
+pub fn poll_readable(fd: RawFd, timeout_ms: i32) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let n = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
         if n < 0 {
             let e = io::Error::last_os_error();
             if e.kind() == io::ErrorKind::Interrupted {
                 continue;
             }
-            return Err(last_err("epoll_wait"));
+            return Err(last_err("poll"));
         }
-        return Ok(n as usize);
+        return Ok(n > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_sockaddrs_round_trip() {
+        let addrs: [SocketAddr; 3] = [
+            "192.0.2.7:8080".parse().unwrap(),
+            "[2001:db8::1]:443".parse().unwrap(),
+            "[fe80::1%3]:80".parse().unwrap(),
+        ];
+        for addr in addrs {
+            let (storage, _) = to_raw_sockaddr(&addr);
+            assert_eq!(from_raw_sockaddr(&storage), Some(addr));
+        }
+
+        let mapped: SocketAddr = "[::ffff:192.0.2.7]:8080".parse().unwrap();
+        let (storage, _) = to_raw_sockaddr(&mapped);
+        assert_eq!(
+            from_raw_sockaddr(&storage),
+            Some("192.0.2.7:8080".parse().unwrap())
+        );
+
+        let unix: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        assert_eq!(from_raw_sockaddr(&unix), None);
+    }
+
+    fn socket_int(fd: RawFd, name: i32) -> i32 {
+        let mut value = 0i32;
+        let mut len = mem::size_of::<i32>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                name,
+                &mut value as *mut i32 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(rc, 0);
+        value
+    }
+
+    #[test]
+    fn listen_socket_applies_buffer_sizes() {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let config = SocketConfig {
+            backlog: 16,
+            recv_buffer: Some(64 * 1024),
+            send_buffer: Some(32 * 1024),
+        };
+        let fd = create_listen_socket(&addr, false, true, &config).unwrap();
+        // Linux reports twice the requested size, to account for bookkeeping.
+        assert!(socket_int(fd, libc::SO_RCVBUF) >= 64 * 1024);
+        assert!(socket_int(fd, libc::SO_SNDBUF) >= 32 * 1024);
+        close_fd(fd);
+
+        let oversized = SocketConfig {
+            recv_buffer: Some(usize::MAX),
+            ..SocketConfig::default()
+        };
+        let err = create_listen_socket(&addr, false, true, &oversized).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 }
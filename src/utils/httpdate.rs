@@ -0,0 +1,255 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. Times
+/// before the epoch clamp to it.
+pub fn format(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let days = secs / 86_400;
+    let of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60
+    )
+}
+
+/// Formats `time` the way the Common Log Format stamps requests, e.g.
+/// `06/Nov/1994:08:49:37 +0000`, always in UTC.
+pub fn format_clf(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(secs / 86_400);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60
+    )
+}
+
+/// Parses any of the three formats recipients must accept: IMF-fixdate
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`), RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and
+/// asctime (`Sun Nov  6 08:49:37 1994`), all in GMT. Dates before 1970, impossible dates
+/// and weekdays that do not match the date are rejected. Two-digit RFC 850 years below
+/// 70 are taken to be in the 2000s.
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    let (weekday, long, date, time) = match tokens.as_slice() {
+        [weekday, day, month, year, time, "GMT"] => {
+            let date = (
+                number(year, 4..=4)?,
+                month_number(month)?,
+                number(day, 2..=2)?,
+            );
+            (weekday.strip_suffix(',')?, false, date, *time)
+        }
+        [weekday, date, time, "GMT"] => {
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            if parts.next().is_some() {
+                return None;
+            }
+            // A fixed pivot rather than RFC 7231 7.1.1.1's window of 50 years from now:
+            // 00-69 are 20xx and 70-99 are 19xx, since dates before 1970 are rejected.
+            let year = number(year, 2..=2)?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            let date = (year, month_number(month)?, number(day, 2..=2)?);
+            (weekday.strip_suffix(',')?, true, date, *time)
+        }
+        [weekday, month, day, time, year] => {
+            let date = (
+                number(year, 4..=4)?,
+                month_number(month)?,
+                number(day, 1..=2)?,
+            );
+            (*weekday, false, date, *time)
+        }
+        _ => return None,
+    };
+
+    let days = days_from_civil(date.0, date.1, date.2)?;
+    let names = if long { &LONG_WEEKDAYS } else { &WEEKDAYS };
+    if names[(days % 7) as usize] != weekday {
+        return None;
+    }
+
+    let mut clock = time.split(':');
+    let hour = number(clock.next()?, 2..=2)?;
+    let minute = number(clock.next()?, 2..=2)?;
+    let second = number(clock.next()?, 2..=2)?;
+    // A leap second is written as :60.
+    if clock.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn number(digits: &str, len: std::ops::RangeInclusive<usize>) -> Option<u64> {
+    if !len.contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn month_number(name: &str) -> Option<u64> {
+    MONTHS
+        .iter()
+        .position(|month| *month == name)
+        .map(|i| i as u64 + 1)
+}
+
+/// Year, month (1-12) and day (1-31) of the proleptic Gregorian date `days` after
+/// 1970-01-01, using Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Inverse of `civil_from_days`; `None` for impossible dates or years before 1970.
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let month_len = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if year < 1970 || day == 0 || day > month_len {
+        return None;
+    }
+
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y % 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146_097 + doe - 719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn formats_imf_fixdate() {
+        assert_eq!(format(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(at(784_111_777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(at(951_782_400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(format(at(4_133_980_799)), "Fri, 31 Dec 2100 23:59:59 GMT");
+    }
+
+    #[test]
+    fn formats_common_log_timestamps() {
+        assert_eq!(format_clf(at(784_111_777)), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(format_clf(at(951_782_400)), "29/Feb/2000:00:00:00 +0000");
+    }
+
+    #[test]
+    fn parses_all_three_formats() {
+        let valid = [
+            ("Sun, 06 Nov 1994 08:49:37 GMT", 784_111_777),
+            ("Sunday, 06-Nov-94 08:49:37 GMT", 784_111_777),
+            ("Sun Nov  6 08:49:37 1994", 784_111_777),
+            ("Thu, 01 Jan 1970 00:00:00 GMT", 0),
+            ("Tue, 29 Feb 2000 00:00:00 GMT", 951_782_400),
+            ("Tuesday, 29-Feb-00 00:00:00 GMT", 951_782_400),
+            ("Tue Feb 29 00:00:00 2000", 951_782_400),
+            ("Fri, 31 Dec 2100 23:59:59 GMT", 4_133_980_799),
+            ("Wed Dec 31 23:59:60 2025", 1_767_225_600),
+        ];
+        for (text, secs) in valid {
+            assert_eq!(parse(text), Some(at(secs)), "{text}");
+        }
+    }
+
+    #[test]
+    fn two_digit_years_pivot_at_70() {
+        assert_eq!(
+            parse("Tuesday, 31-Dec-69 23:59:59 GMT"),
+            Some(at(3_155_759_999))
+        );
+        assert_eq!(parse("Thursday, 01-Jan-70 00:00:00 GMT"), Some(at(0)));
+        assert_eq!(parse("Wednesday, 31-Dec-69 23:59:59 GMT"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        let invalid = [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 +0000",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun 06 Nov 1994 08:49:37 GMT",
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "Sun, 06 nov 1994 08:49:37 GMT",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Thu, 29 Feb 2001 00:00:00 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun, 06-Nov-94 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sunday, 06 Nov 94 08:49:37 GMT",
+            "Sun Nov 6 08:49:37 1994 GMT",
+            "Sun Nov 6 08:49:37 94",
+            "1994-11-06T08:49:37Z",
+        ];
+        for text in invalid {
+            assert_eq!(parse(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for secs in [0, 59, 86_399, 784_111_777, 1_700_000_000, 4_133_980_799] {
+            assert_eq!(parse(&format(at(secs))), Some(at(secs)));
+        }
+    }
+}
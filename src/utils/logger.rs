@@ -24,28 +24,28 @@ macro_rules! log {
 #[macro_export]
 macro_rules! info {
     ($msg:expr $(, $key:expr => $val:expr )* ) => {{
-        log!("INFO", $msg $(, $key => $val )* );
+        $crate::log!("INFO", $msg $(, $key => $val )* );
     }}
 }
 
 #[macro_export]
 macro_rules! warn {
     ($msg:expr $(, $key:expr => $val:expr )* ) => {{
-        log!("WARN", $msg $(, $key => $val )* );
+        $crate::log!("WARN", $msg $(, $key => $val )* );
     }}
 }
 
 #[macro_export]
 macro_rules! debug {
     ($msg:expr $(, $key:expr => $val:expr )* ) => {{
-        log!("DEBUG", $msg $(, $key => $val )* );
+        $crate::log!("DEBUG", $msg $(, $key => $val )* );
     }}
 }
 
 #[macro_export]
 macro_rules! error {
     ($msg:expr $(, $key:expr => $val:expr )* ) => {{
-        log!("ERROR", $msg $(, $key => $val )* );
+        $crate::log!("ERROR", $msg $(, $key => $val )* );
     }}
 }
 